@@ -1,28 +1,124 @@
 use crate::kmath::*;
+use crate::dsp::*;
+use crate::wav::*;
+use std::sync::Arc;
+use std::collections::HashMap;
 
 use cpal::Stream;
 use cpal::traits::*;
 use ringbuf::*;
 
-pub struct Audio {
+// where AudioMessages go. cpal when theres a device, otherwise null so the game still runs headless / without sound
+// recording keeps everything it was sent so tests can check what a frame asked for
+pub trait AudioBackend {
+    fn send(&mut self, m: AudioMessage);
+    fn recorded(&self) -> &[AudioMessage] { &[] }
+}
+
+pub struct CpalBackend {
     stream: Stream,
-    channel: Producer<SoundCommand>,
+    channel: Producer<AudioMessage>,
 }
 
-impl Audio {
-    pub fn new() -> Audio {
-        let rb = RingBuffer::<SoundCommand>::new(50);
-        let (mut prod, mut cons) = rb.split();
-        let mut audio = Audio {
-            stream: stream_setup_for(sample_next, cons).expect("no can make stream"),
+impl CpalBackend {
+    pub fn new() -> Result<CpalBackend, anyhow::Error> {
+        let rb = RingBuffer::<AudioMessage>::new(256);
+        let (prod, cons) = rb.split();
+        let backend = CpalBackend {
+            stream: stream_setup_for(sample_next, cons)?,
             channel: prod,
         };
-        audio.stream.play().expect("cant play audio stream");
-        audio
+        backend.stream.play()?;
+        Ok(backend)
     }
+}
 
-    pub fn handle_command(&mut self, sc: SoundCommand) {
-        self.channel.push(sc);
+impl AudioBackend for CpalBackend {
+    // if the callback has fallen this far behind dropping a sound is the least bad option
+    fn send(&mut self, m: AudioMessage) {
+        let _ = self.channel.push(m);
+    }
+}
+
+pub struct NullBackend;
+
+impl AudioBackend for NullBackend {
+    fn send(&mut self, _m: AudioMessage) {}
+}
+
+#[derive(Default)]
+pub struct RecordingBackend {
+    pub messages: Vec<AudioMessage>,
+}
+
+impl AudioBackend for RecordingBackend {
+    fn send(&mut self, m: AudioMessage) {
+        self.messages.push(m);
+    }
+    fn recorded(&self) -> &[AudioMessage] {
+        &self.messages
+    }
+}
+
+pub struct Audio {
+    backend: Box<dyn AudioBackend>,
+    fallbacks: HashMap<i32, SoundDesc>,     // by sample id, for samples that never loaded
+}
+
+impl Audio {
+    pub fn new() -> Audio {
+        match CpalBackend::new() {
+            Ok(b) => Audio::with_backend(Box::new(b)),
+            Err(e) => {
+                println!("warning: couldnt open audio output ({}), continuing without sound", e);
+                Audio::with_backend(Box::new(NullBackend))
+            },
+        }
+    }
+
+    pub fn with_backend(backend: Box<dyn AudioBackend>) -> Audio {
+        Audio { backend, fallbacks: HashMap::new() }
+    }
+
+    pub fn recording() -> Audio {
+        Audio::with_backend(Box::new(RecordingBackend::default()))
+    }
+
+    pub fn recorded(&self) -> &[AudioMessage] {
+        self.backend.recorded()
+    }
+
+    // load on this thread, the mixer just gets a pointer
+    pub fn load_sample(&mut self, id: u32, smp: Arc<Sample>) {
+        self.backend.send(AudioMessage::LoadSample(id, smp));
+    }
+
+    // commands only, in the order they were sent
+    pub fn recorded_sounds(&self) -> Vec<SoundCommand> {
+        self.recorded().iter().filter_map(|m| match m {
+            AudioMessage::Sound(sc) => Some(*sc),
+            _ => None,
+        }).collect()
+    }
+
+    // sounds that would play sample id play sd instead, so a missing file isnt a silent voice
+    pub fn sample_fallback(&mut self, id: i32, sd: SoundDesc) {
+        self.fallbacks.insert(id, sd);
+    }
+
+    pub fn handle_command(&mut self, mut sc: SoundCommand) {
+        if let Some(fallback) = self.fallbacks.get(&sc.sd.sample) {
+            sc.sd = SoundDesc { release: sc.sd.release, ..*fallback };
+        }
+        self.backend.send(AudioMessage::Sound(sc));
+    }
+
+    pub fn set_listener(&mut self, l: Listener) {
+        self.backend.send(AudioMessage::Listener(l));
+    }
+
+    pub fn set_bus(&mut self, bc: BusConfig) {
+        self.backend.send(AudioMessage::Bus(bc));
     }
 }
 
@@ -52,12 +148,200 @@ pub struct SoundDesc {
     pub cdr: f32,
     pub aout: f32,
     pub release: bool,
+    pub pan: f32,   // -1 left .. 1 right
+    pub filter: FilterKind,
+    pub fc: f32,    // filter cutoff hz
+    pub fq: f32,    // filter resonance
+    pub fenv: f32,  // octaves the cutoff opens by at the top of the envelope
+
+    // sample voices, when sample != -1 it plays that loaded sample instead of the additive oscillators
+    pub sample: i32,
+    pub pitch: f32,         // playback rate, 2 is an octave up
+    pub pitch_var: f32,     // random +- semitones each trigger
+    pub loop_start: f32,    // seconds, loops while held if loop_end > loop_start
+    pub loop_end: f32,
+
+    // voice limiting: higher priority can steal from lower, at most max_instances (if > 0) of a group at once
+    pub priority: i32,
+    pub group: i32,
+    pub max_instances: i32,
+}
+
+// balance rather than constant power so a centred sound is as loud as it was in mono
+pub fn pan_gains(pan: f32) -> (f32, f32) {
+    let pan = pan.clamp(-1.0, 1.0);
+    ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0))
 }
 
 #[derive(Clone, Copy, Debug)]
 pub struct SoundCommand {
     pub sd: SoundDesc,
     pub id: u32,
+    pub pos: Option<Vec2>,  // world position, None plays flat
+    pub occluded: bool,     // theres rock between it and the listener, the game knows the level so it decides
+}
+
+impl SoundCommand {
+    pub fn new(sd: SoundDesc, id: u32) -> SoundCommand {
+        SoundCommand { sd, id, pos: None, occluded: false }
+    }
+
+    pub fn at(self, pos: Vec2, occluded: bool) -> SoundCommand {
+        SoundCommand { pos: Some(pos), occluded, ..self }
+    }
+}
+
+// where the ears are. half_width is half the visible world width, something at the screen edge pans all the way
+#[derive(Clone, Copy, Debug)]
+pub struct Listener {
+    pub pos: Vec2,
+    pub half_width: f32,
+}
+
+impl Default for Listener {
+    fn default() -> Self {
+        Listener { pos: Vec2::zero(), half_width: 1.0 }
+    }
+}
+
+// mono pcm at its own rate, shared with the audio thread so loading never happens there
+#[derive(Debug)]
+pub struct Sample {
+    pub sample_rate: f32,
+    pub data: Vec<f32>,
+}
+
+impl Sample {
+    // mixes down to mono
+    pub fn from_wav(w: &WavData) -> Sample {
+        let nc = w.nchannels as usize;
+        let data = w.samples.chunks(nc).map(|frame| frame.iter().sum::<f32>() / nc as f32).collect();
+        Sample { sample_rate: w.sample_rate as f32, data }
+    }
+
+    pub fn load(path: &str) -> Result<Sample, anyhow::Error> {
+        Ok(Sample::from_wav(&read_wav(path)?))
+    }
+
+    // linear interpolation, 0 off either end
+    pub fn at(&self, pos: f64) -> f32 {
+        if pos < 0.0 {
+            return 0.0;
+        }
+        let i = pos as usize;
+        let t = (pos - i as f64) as f32;
+        let a = self.data.get(i).copied().unwrap_or(0.0);
+        let b = self.data.get(i + 1).copied().unwrap_or(0.0);
+        lerp(a, b, t)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum AudioMessage {
+    Sound(SoundCommand),
+    Listener(Listener),
+    Bus(BusConfig),
+    LoadSample(u32, Arc<Sample>),
+}
+
+pub const MAX_SAMPLES: usize = 64;
+
+pub const OCCLUSION_CUTOFF: f32 = 500.0;
+pub const OCCLUSION_GAIN: f32 = 0.6;
+
+// gain and pan for a source relative to the listener
+// full volume out to a quarter screen then falling off with distance squared
+pub fn spatialize(l: &Listener, pos: Vec2) -> (f32, f32) {
+    let u = pos - l.pos;
+    let ref_dist = l.half_width * 0.5;
+    let d = (u.magnitude() - ref_dist).max(0.0);
+    let gain = 1.0 / (1.0 + (d / ref_dist).powi(2));
+    let pan = (u.x / l.half_width).clamp(-1.0, 1.0);
+    (gain, pan)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EnvStage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Done,
+}
+
+// linear adsr stepped one sample at a time
+// every stage moves from wherever the level currently is so retriggers and early releases never jump (no clicks)
+// one shots (sd.release) go straight into release once the decay finishes, held notes sit on sustain until release()
+#[derive(Clone, Copy, Debug)]
+pub struct Envelope {
+    pub stage: EnvStage,
+    pub level: f32,
+    release_step: f32,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Envelope {
+            stage: EnvStage::Attack,
+            level: 0.0,
+            release_step: 0.0,
+        }
+    }
+}
+
+impl Envelope {
+    pub fn tick(&mut self, sd: &SoundDesc, dt: f32) -> f32 {
+        // a zero length stage takes one sample
+        let step = |secs: f32| if secs > dt { dt / secs } else { 1.0 };
+        match self.stage {
+            EnvStage::Attack => {
+                self.level += step(sd.ea);
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = EnvStage::Decay;
+                }
+            },
+            EnvStage::Decay => {
+                self.level -= (1.0 - sd.es) * step(sd.ed);
+                if self.level <= sd.es {
+                    self.level = sd.es;
+                    self.stage = EnvStage::Sustain;
+                }
+            },
+            EnvStage::Sustain => {
+                // es can change under a held note, glide to it rather than snapping
+                self.level += (sd.es - self.level) * step(0.01);
+                if sd.release {
+                    self.release(sd, dt);
+                }
+            },
+            EnvStage::Release => {
+                self.level -= self.release_step;
+                if self.level <= 0.0 {
+                    self.level = 0.0;
+                    self.stage = EnvStage::Done;
+                }
+            },
+            EnvStage::Done => {},
+        }
+        self.level
+    }
+
+    pub fn release(&mut self, sd: &SoundDesc, dt: f32) {
+        if self.stage == EnvStage::Release || self.stage == EnvStage::Done {
+            return;
+        }
+        self.stage = EnvStage::Release;
+        self.release_step = self.level * if sd.er > dt { dt / sd.er } else { 1.0 };
+    }
+
+    pub fn retrigger(&mut self) {
+        self.stage = EnvStage::Attack;
+    }
+
+    pub fn done(&self) -> bool {
+        self.stage == EnvStage::Done
+    }
 }
 
 pub struct Channel {
@@ -65,28 +349,60 @@ pub struct Channel {
     pub age: f32,
     pub phases: Vec<f32>,
     pub id: u32,
+    pub env: Envelope,
+    pub pos: Option<Vec2>,
+    pub occluded: bool,
+    lp: f32,
+    svf: Svf,
+    playhead: f64,
+    rate: f32,
+    finished: bool,
 }
 
 impl Channel {
-    pub fn tick(&mut self) -> f32 {
-        self.age += 1.0 / 44100.0;
+    pub fn tick(&mut self, sample_rate: f32, samples: &[Option<Arc<Sample>>]) -> f32 {
+        let dt = 1.0 / sample_rate;
+        self.age += dt;
 
         let sd = self.sd;
 
-        let voices_len = sd.voices.floor() as usize;
-        let n_len = sd.n.floor() as usize;
-        self.phases.resize(voices_len*n_len, 0.0);  // or resize with random phases, is better
+        let (voices_len, n_len) = partial_counts(&sd);
+        self.phases.resize(voices_len*n_len, 0.0);  // within the preallocated capacity. or resize with random phases, is better
 
         // pre compression
         let mut acc = 0.0;
 
         let a_vol = db_to_vol(sd.amp);
 
-        let a_env = 1.0;
+        let is_sample = sd.sample >= 0;
+        // one shot samples play to the end of the sample rather than auto releasing
+        let a_env = if is_sample {
+            self.env.tick(&SoundDesc { release: false, ..sd }, dt)
+        } else {
+            self.env.tick(&sd, dt)
+        };
 
         let a_voices = 1.0 / voices_len as f32;
 
-        for detune_voice_num in 0..voices_len {
+        if is_sample {
+            match samples.get(sd.sample as usize).and_then(|s| s.as_ref()) {
+                Some(smp) => {
+                    acc = a_env * a_vol * smp.at(self.playhead);
+                    self.playhead += (self.rate * smp.sample_rate / sample_rate) as f64;
+                    let ls = (sd.loop_start * smp.sample_rate) as f64;
+                    let le = ((sd.loop_end * smp.sample_rate) as f64).min(smp.data.len() as f64);
+                    if le > ls && self.playhead >= le && self.env.stage != EnvStage::Done {
+                        self.playhead -= le - ls;
+                    } else if self.playhead >= smp.data.len() as f64 {
+                        self.finished = true;
+                    }
+                },
+                // not loaded, nothing to play
+                None => self.finished = true,
+            }
+        }
+
+        for detune_voice_num in 0..if is_sample { 0 } else { voices_len } {
             for n in 0..n_len {
                 let a_roll = 1.0 / ((n+1) as f32).powf(sd.troll);
 
@@ -95,11 +411,13 @@ impl Channel {
                 let f = f * detune_interval.powf(detune_voice_num as f32);
 
                 let idx = detune_voice_num * n_len + n;
-                self.phases[idx] = (self.phases[idx] + f / 44100.0).fract();
+                self.phases[idx] = (self.phases[idx] + f * dt).fract();
                 acc += a_voices * a_env * a_roll * a_vol * (2.0 * PI * self.phases[idx]).sin();
             }
         }
 
+        let cutoff = sd.fc * 2.0f32.powf(sd.fenv * a_env);
+        acc = self.svf.process(acc, sd.filter, cutoff, sd.fq, sample_rate);
 
         // now do compression
         // change db value or amplitude value?
@@ -126,88 +444,269 @@ impl Channel {
     }
 
     pub fn should_remove(&self) -> bool {
-        self.sd.release && self.age > self.sd.er
+        self.env.done() || self.finished
     }
 }
 
-#[derive(Default)]
+pub const MAX_CHANNELS: usize = 64;
+pub const MAX_PHASES: usize = 64;
+
+// voices * harmonics, capped so a channel never outgrows its preallocated phase buffer
+fn partial_counts(sd: &SoundDesc) -> (usize, usize) {
+    let voices_len = (sd.voices.floor() as usize).clamp(1, MAX_PHASES);
+    let n_len = (sd.n.floor() as usize).clamp(1, MAX_PHASES / voices_len);
+    (voices_len, n_len)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StealPolicy {
+    Oldest,
+    Quietest,
+}
+
+// runs on the audio thread so nothing in here allocates or prints after new()
+// channels are a fixed size voice pool: MAX_CHANNELS phase buffers are made up front and recycled
+// when its full a new sound takes over the least important voice that isnt above it in priority, or is dropped
 pub struct Mixer {
+    pub steal_policy: StealPolicy,
+    pub sample_rate: f32,
     pub sample_count: u64,
+    pub listener: Listener,
+    pub bus: MasterBus,
     pub channels: Vec<Channel>,
+    spare_phases: Vec<Vec<f32>>,
+    samples: Vec<Option<Arc<Sample>>>,
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Mixer::new(44100.0)
+    }
 }
 
 impl Mixer {
+    pub fn new(sample_rate: f32) -> Mixer {
+        Mixer {
+            steal_policy: StealPolicy::Quietest,
+            sample_rate,
+            sample_count: 0,
+            listener: Listener::default(),
+            bus: MasterBus::new(sample_rate),
+            channels: Vec::with_capacity(MAX_CHANNELS),
+            spare_phases: (0..MAX_CHANNELS).map(|_| Vec::with_capacity(MAX_PHASES)).collect(),
+            samples: vec![None; MAX_SAMPLES],
+        }
+    }
+
+    pub fn handle_message(&mut self, m: AudioMessage) {
+        match m {
+            AudioMessage::Sound(sc) => self.handle_command(sc),
+            AudioMessage::Listener(l) => self.listener = l,
+            AudioMessage::Bus(bc) => self.bus.config = bc,
+            AudioMessage::LoadSample(id, smp) => self.load_sample(id, smp),
+        }
+    }
+
+    // replacing a sample drops the old Arc here, the game keeps its own copy so that never frees on this thread
+    pub fn load_sample(&mut self, id: u32, smp: Arc<Sample>) {
+        if let Some(slot) = self.samples.get_mut(id as usize) {
+            *slot = Some(smp);
+        }
+    }
+
+    fn trigger_rate(&self, sd: &SoundDesc, id: u32) -> f32 {
+        let r = krand(khash(id ^ khash(self.sample_count as u32))) * 2.0 - 1.0;
+        sd.pitch * 2.0f32.powf(r * sd.pitch_var / 12.0)
+    }
+
     pub fn handle_command(&mut self, sc: SoundCommand) {
-        println!("handle command {:?}", sc);
         for i in 0..self.channels.len() {
             // if its already playing we may want to blend
             if self.channels[i].id == sc.id {
+                let ch = &mut self.channels[i];
+                if sc.sd.release && !ch.sd.release {
+                    // held note let go: keep the old desc so it doesnt change timbre while fading
+                    ch.sd.release = true;
+                    ch.env.release(&ch.sd, 1.0 / self.sample_rate);
+                } else {
+                    // retrigger, the envelope attacks from its current level
+                    ch.sd = sc.sd;
+                    ch.pos = sc.pos;
+                    ch.occluded = sc.occluded;
+                    ch.age = 0.0;
+                    ch.env.retrigger();
+                    if sc.sd.sample >= 0 {
+                        ch.playhead = 0.0;
+                        ch.finished = false;
+                        let rate = self.trigger_rate(&sc.sd, sc.id);
+                        self.channels[i].rate = rate;
+                    }
+                }
+                return;
+            }
+        }
 
-                self.channels[i].sd = sc.sd;
-
-                self.channels[i].age = 0.0;
+        // too many of this kind already: the oldest one makes way
+        if sc.sd.max_instances > 0 && sc.sd.group >= 0 {
+            let mut count = 0;
+            let mut oldest: Option<usize> = None;
+            for (i, ch) in self.channels.iter().enumerate() {
+                if ch.sd.group == sc.sd.group {
+                    count += 1;
+                    if oldest.map(|o| self.channels[o].age < ch.age).unwrap_or(true) {
+                        oldest = Some(i);
+                    }
+                }
+            }
+            if count >= sc.sd.max_instances {
+                if let Some(i) = oldest {
+                    self.replace_voice(i, sc);
+                }
                 return;
             }
         }
-        let voices_len = sc.sd.voices.floor() as usize;
-        let n_len = sc.sd.n.floor() as usize;
-        self.channels.push(Channel {
+
+        match self.spare_phases.pop() {
+            Some(phases) => {
+                let ch = self.new_voice(sc, phases);
+                self.channels.push(ch);
+            },
+            None => {
+                if let Some(i) = self.steal_candidate(sc.sd.priority) {
+                    self.replace_voice(i, sc);
+                }
+            },
+        }
+    }
+
+    fn new_voice(&self, sc: SoundCommand, mut phases: Vec<f32>) -> Channel {
+        phases.clear();
+        Channel {
             sd: sc.sd,
             id: sc.id,
             age: 0.0,
-            phases: vec![0.0; voices_len*n_len], // todo preallocate max phases for detune
-        });
+            phases,
+            env: Envelope::default(),
+            pos: sc.pos,
+            occluded: sc.occluded,
+            lp: 0.0,
+            svf: Svf::default(),
+            playhead: 0.0,
+            rate: self.trigger_rate(&sc.sd, sc.id),
+            finished: false,
+        }
+    }
+
+    fn replace_voice(&mut self, i: usize, sc: SoundCommand) {
+        let phases = std::mem::take(&mut self.channels[i].phases);
+        self.channels[i] = self.new_voice(sc, phases);
     }
 
-    pub fn tick(&mut self) -> f32 {
+    // how loud a voice is right now, for quietest stealing
+    fn loudness(&self, ch: &Channel) -> f32 {
+        let spatial = ch.pos.map(|p| spatialize(&self.listener, p).0).unwrap_or(1.0);
+        ch.env.level * db_to_vol(ch.sd.amp) * spatial
+    }
+
+    // lowest priority first, then by the steal policy. never takes a voice above the new sounds priority
+    fn steal_candidate(&self, priority: i32) -> Option<usize> {
+        let mut best: Option<(usize, i32, f32)> = None;
+        for (i, ch) in self.channels.iter().enumerate() {
+            if ch.sd.priority > priority {
+                continue;
+            }
+            let score = match self.steal_policy {
+                StealPolicy::Oldest => -ch.age,
+                StealPolicy::Quietest => self.loudness(ch),
+            };
+            let better = match best {
+                None => true,
+                Some((_, bp, bs)) => ch.sd.priority < bp || (ch.sd.priority == bp && score < bs),
+            };
+            if better {
+                best = Some((i, ch.sd.priority, score));
+            }
+        }
+        best.map(|(i, _, _)| i)
+    }
+
+    // one stereo frame
+    pub fn tick(&mut self) -> (f32, f32) {
         self.sample_count += 1;
 
         let mut i = self.channels.len();
-        if i == 0 { return 0.0 }
+        if i == 0 { return self.bus.process((0.0, 0.0)) }
         i -= 1;
-        let mut acc = 0.0;
+        let mut acc = (0.0, 0.0);
+        // one pole lowpass for sounds behind walls
+        let lp_k = 1.0 - (-2.0 * PI * OCCLUSION_CUTOFF / self.sample_rate).exp();
         loop {
-            acc += self.channels[i].tick();
-            
-            if self.channels[i].should_remove() { 
-                println!("removing {}", i);
-                self.channels.swap_remove(i);
+            let ch = &mut self.channels[i];
+            let mut x = ch.tick(self.sample_rate, &self.samples);
+            let mut pan = ch.sd.pan;
+            if let Some(pos) = ch.pos {
+                let (gain, spatial_pan) = spatialize(&self.listener, pos);
+                x *= gain;
+                pan = (pan + spatial_pan).clamp(-1.0, 1.0);
+            }
+            if ch.occluded {
+                ch.lp += lp_k * (x - ch.lp);
+                x = ch.lp * OCCLUSION_GAIN;
+            }
+            let (gl, gr) = pan_gains(pan);
+            acc.0 += x * gl;
+            acc.1 += x * gr;
+
+            if self.channels[i].should_remove() {
+                let ch = self.channels.swap_remove(i);
+                self.spare_phases.push(ch.phases);
             }
 
             if i == 0 { break; }
             i -= 1;
         }
-        acc
+        self.bus.process(acc)
     }
 }
 
 
 
-// pub fn env_amplitude(a: f32, d: f32, s: f32, r: f32, curr_sample: u32, sample_rate: u32, released_sample: Option<u32>) -> f32 {
-//     // +1 for useful recursion
-//     let A = self.a * sample_rate as f32;
-//     let D = self.d * sample_rate as f32;
-//     let S = self.s;
-//     let R = self.r * sample_rate as f32;
-
-//     if let Some(released_on) = released_sample {
-//         let num_released = curr_sample - released_on;
-//         let release_value = self.amplitude(released_on, sample_rate, None);
-//         return lerp(release_value, 0.0, num_released as f32 / R).max(0.0);
-//     }
-//     if curr_sample as f32 <= A {
-//         return lerp(0.0, 1.0, curr_sample as f32 / A);
-//     }
-//     if curr_sample as f32 <= D + A {
-//         return lerp(1.0, S, (curr_sample as f32 - A)/D);
-//     }
-//     S
-// }
-
+pub const OFFLINE_SAMPLE_RATE: u32 = 44100;
 
+// drive the mixer without a device, for auditioning sounds and testing them
+// commands are (seconds, command) and get applied on the sample they land on
+// output is interleaved stereo
+pub fn render_offline(commands: &[(f32, SoundCommand)], duration: f32, sample_rate: u32) -> Vec<f32> {
+    let mut commands = commands.to_vec();
+    commands.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    let n = (duration * sample_rate as f32).round() as usize;
+    let mut mixer = Mixer::new(sample_rate as f32);
+    let mut out = Vec::with_capacity(n * 2);
+    let mut next = 0;
+    for i in 0..n {
+        while next < commands.len() && (commands[next].0 * sample_rate as f32).round() as usize <= i {
+            mixer.handle_command(commands[next].1);
+            next += 1;
+        }
+        let (l, r) = mixer.tick();
+        out.push(l);
+        out.push(r);
+    }
+    out
+}
 
+// one shots play out, held sounds are let go after hold seconds
+pub fn render_desc(sd: SoundDesc, hold: f32, sample_rate: u32) -> Vec<f32> {
+    let tail = 0.1;
+    if sd.release {
+        render_offline(&[(0.0, SoundCommand::new(sd, 0))], sd.ea + sd.ed + sd.er + tail, sample_rate)
+    } else {
+        let release = SoundCommand::new(SoundDesc { release: true, ..sd }, 0);
+        render_offline(&[(0.0, SoundCommand::new(sd, 0)), (hold, release)], hold + sd.er + tail, sample_rate)
+    }
+}
 
-fn sample_next(o: &mut SampleRequestOptions) -> f32 {
+fn sample_next(o: &mut SampleRequestOptions) -> (f32, f32) {
     o.mixer.tick()
 }
 
@@ -215,16 +714,14 @@ pub struct SampleRequestOptions {
     pub sample_rate: f32,
     pub nchannels: usize,
 
-    // pub filter: Filter,
-
     pub mixer: Mixer,
 
-    pub channel: Consumer<SoundCommand>,
+    pub channel: Consumer<AudioMessage>,
 }
 
-pub fn stream_setup_for<F>(on_sample: F, channel: Consumer<SoundCommand>) -> Result<cpal::Stream, anyhow::Error>
+pub fn stream_setup_for<F>(on_sample: F, channel: Consumer<AudioMessage>) -> Result<cpal::Stream, anyhow::Error>
 where
-    F: FnMut(&mut SampleRequestOptions) -> f32 + std::marker::Send + 'static + Copy,
+    F: FnMut(&mut SampleRequestOptions) -> (f32, f32) + std::marker::Send + 'static + Copy,
 {
     let (_host, device, config) = host_device_setup()?;
 
@@ -254,11 +751,11 @@ pub fn stream_make<T, F>(
     device: &cpal::Device,
     config: &cpal::StreamConfig,
     on_sample: F,
-    channel: Consumer<SoundCommand>,
+    channel: Consumer<AudioMessage>,
 ) -> Result<cpal::Stream, anyhow::Error>
 where
     T: cpal::Sample,
-    F: FnMut(&mut SampleRequestOptions) -> f32 + std::marker::Send + 'static + Copy,
+    F: FnMut(&mut SampleRequestOptions) -> (f32, f32) + std::marker::Send + 'static + Copy,
 {
     let sample_rate = config.sample_rate.0 as f32;
     let nchannels = config.channels as usize;
@@ -266,7 +763,7 @@ where
         sample_rate,
         nchannels,
 
-        mixer: Mixer::default(),
+        mixer: Mixer::new(sample_rate),
 
         channel,
     };
@@ -286,15 +783,305 @@ where
 fn on_window<T, F>(output: &mut [T], request: &mut SampleRequestOptions, mut on_sample: F)
 where
     T: cpal::Sample,
-    F: FnMut(&mut SampleRequestOptions) -> f32 + std::marker::Send + 'static,
+    F: FnMut(&mut SampleRequestOptions) -> (f32, f32) + std::marker::Send + 'static,
 {
-    if let Some(sc) = request.channel.pop() {
-        request.mixer.handle_command(sc);
+    while let Some(m) = request.channel.pop() {
+        request.mixer.handle_message(m);
     }
     for frame in output.chunks_mut(request.nchannels) {
-        let value: T = cpal::Sample::from::<f32>(&on_sample(request));
-        for sample in frame.iter_mut() {
-            *sample = value;
+        let (l, r) = on_sample(request);
+        if frame.len() == 1 {
+            frame[0] = cpal::Sample::from::<f32>(&(0.5 * (l + r)));
+            continue;
+        }
+        // anything past the first two channels (surround etc) stays silent
+        for (i, sample) in frame.iter_mut().enumerate() {
+            let x = match i {
+                0 => l,
+                1 => r,
+                _ => 0.0,
+            };
+            *sample = cpal::Sample::from::<f32>(&x);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+fn test_desc(ea: f32, ed: f32, es: f32, er: f32, release: bool) -> SoundDesc {
+    SoundDesc { f: 440.0, n: 1.0, troll: 1.0, ea, ed, es, er, detune: 0.0, voices: 1.0, amp: 0.0, cut: -60.0, cur: 1.0, cdt: 0.0, cdr: 1.0, aout: 1.0, release, pan: 0.0, filter: FilterKind::Off, fc: 1000.0, fq: 0.707, fenv: 0.0, sample: -1, pitch: 1.0, pitch_var: 0.0, loop_start: 0.0, loop_end: 0.0, priority: 1, group: -1, max_instances: 0 }
+}
+
+#[test]
+fn test_envelope_adsr() {
+    // dt of 1/10 so every stage is a handful of samples
+    let dt = 0.1;
+    let sd = test_desc(0.4, 0.2, 0.5, 0.5, false);
+    let mut env = Envelope::default();
+    let levels: Vec<f32> = (0..10).map(|_| env.tick(&sd, dt)).collect();
+    let expected = [0.25, 0.5, 0.75, 1.0, 0.75, 0.5, 0.5, 0.5, 0.5, 0.5];
+    for (l, e) in levels.iter().zip(expected.iter()) {
+        assert!((l - e).abs() < 1e-5, "{:?}", levels);
+    }
+    assert_eq!(env.stage, EnvStage::Sustain);
+
+    env.release(&sd, dt);
+    let levels: Vec<f32> = (0..6).map(|_| env.tick(&sd, dt)).collect();
+    let expected = [0.4, 0.3, 0.2, 0.1, 0.0, 0.0];
+    for (l, e) in levels.iter().zip(expected.iter()) {
+        assert!((l - e).abs() < 1e-5, "{:?}", levels);
+    }
+    assert!(env.done());
+}
+
+#[test]
+fn test_envelope_one_shot_and_early_release() {
+    let dt = 0.1;
+    // one shot releases by itself after the decay
+    let sd = test_desc(0.1, 0.1, 0.5, 0.2, true);
+    let mut env = Envelope::default();
+    let levels: Vec<f32> = (0..6).map(|_| env.tick(&sd, dt)).collect();
+    let expected = [1.0, 0.5, 0.5, 0.25, 0.0, 0.0];
+    for (l, e) in levels.iter().zip(expected.iter()) {
+        assert!((l - e).abs() < 1e-5, "{:?}", levels);
+    }
+    assert!(env.done());
+
+    // releasing mid attack fades from where it got to
+    let sd = test_desc(1.0, 0.1, 0.5, 0.3, false);
+    let mut env = Envelope::default();
+    env.tick(&sd, dt);
+    env.tick(&sd, dt);
+    env.tick(&sd, dt);
+    env.release(&sd, dt);
+    let levels: Vec<f32> = (0..3).map(|_| env.tick(&sd, dt)).collect();
+    let expected = [0.2, 0.1, 0.0];
+    for (l, e) in levels.iter().zip(expected.iter()) {
+        assert!((l - e).abs() < 1e-5, "{:?}", levels);
+    }
+}
+
+#[test]
+fn test_mixer_release_is_click_free() {
+    let sd = test_desc(0.01, 0.05, 0.6, 0.05, false);
+    let mut m = Mixer::default();
+    m.handle_command(SoundCommand::new(sd, 7));
+    let mut prev = 0.0;
+    let mut max_jump: f32 = 0.0;
+    let mut n = 0;
+    for i in 0..44100 {
+        if i == 10000 {
+            m.handle_command(SoundCommand::new(SoundDesc { release: true, ..sd }, 7));
+        }
+        let x = m.tick().0;
+        max_jump = max_jump.max((x - prev).abs());
+        prev = x;
+        n = i;
+        if m.channels.is_empty() {
+            break;
+        }
+    }
+    // 440hz at full scale moves at most 2 pi 440 / 44100 per sample, anything bigger is a click
+    assert!(max_jump < 2.0 * PI * 440.0 / 44100.0 + 1e-3, "jump {}", max_jump);
+    // released after 10000 samples, 0.05s release is about 2205 more
+    assert!(n > 12000 && n < 12400, "removed at {}", n);
+    assert!(prev.abs() < 1e-3);
+}
+
+#[test]
+fn test_render_offline() {
+    let sd = test_desc(0.01, 0.05, 0.5, 0.1, false);
+    let release = SoundDesc { release: true, ..sd };
+    let out = render_offline(&[(0.0, SoundCommand::new(sd, 1)), (0.5, SoundCommand::new(release, 1))], 1.0, OFFLINE_SAMPLE_RATE);
+    // stereo, so 2 samples a frame
+    let sr = OFFLINE_SAMPLE_RATE as usize * 2;
+    assert_eq!(out.len(), sr);
+
+    let peak = |s: &[f32]| s.iter().fold(0.0f32, |acc, x| acc.max(x.abs()));
+    // 0db sine peaks at the top of the attack, then sits at the sustain level
+    assert!((peak(&out[..sr / 20]) - 1.0).abs() < 0.02);
+    assert!((peak(&out[sr / 4..sr / 2]) - 0.5).abs() < 0.02);
+    // silent once the release is over
+    assert_eq!(peak(&out[sr * 6 / 10..]), 0.0);
+}
+
+#[test]
+fn test_render_presets() {
+    for (name, sd) in crate::sounds::PRESETS.iter() {
+        let out = render_desc(*sd, 0.5, OFFLINE_SAMPLE_RATE);
+        let peak = out.iter().fold(0.0f32, |acc, x| acc.max(x.abs()));
+        assert!(peak > 0.01 && peak <= 1.0, "{} peak {}", name, peak);
+        let tail = OFFLINE_SAMPLE_RATE as usize / 20;
+        assert!(out[out.len() - tail..].iter().all(|x| *x == 0.0), "{} doesnt end", name);
+    }
+}
+
+#[test]
+fn test_mixer_sample_rate_and_pan() {
+    // same pitch and length whatever the device rate
+    for sample_rate in [22050, 44100, 48000] {
+        let sd = test_desc(0.0, 0.0, 1.0, 0.0, false);
+        let out = render_offline(&[(0.0, SoundCommand::new(sd, 1))], 1.0, sample_rate);
+        assert_eq!(out.len(), sample_rate as usize * 2);
+        let left: Vec<f32> = out.iter().step_by(2).cloned().collect();
+        let crossings = left.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        assert!((crossings as i32 - 440).abs() <= 1, "{} crossings at {}", crossings, sample_rate);
+    }
+
+    let sd = SoundDesc { pan: -1.0, ..test_desc(0.0, 0.0, 1.0, 0.0, false) };
+    let out = render_offline(&[(0.0, SoundCommand::new(sd, 1))], 0.1, OFFLINE_SAMPLE_RATE);
+    assert!(out.iter().step_by(2).any(|x| x.abs() > 0.5));
+    assert!(out.iter().skip(1).step_by(2).all(|x| *x == 0.0));
+}
+
+#[test]
+fn test_mixer_reuses_channels() {
+    let mut m = Mixer::new(1000.0);
+    let sd = test_desc(0.0, 0.0, 1.0, 0.0, true);
+    // more one shots than channels, the extras steal instead of growing the vec
+    for id in 0..MAX_CHANNELS as u32 + 10 {
+        m.handle_command(SoundCommand::new(sd, id));
+    }
+    assert_eq!(m.channels.len(), MAX_CHANNELS);
+    assert_eq!(m.channels.capacity(), MAX_CHANNELS);
+    for _ in 0..10 {
+        m.tick();
+    }
+    assert!(m.channels.is_empty());
+    m.handle_command(SoundCommand::new(sd, 1));
+    assert_eq!(m.channels.len(), 1);
+    assert!(m.channels.capacity() == MAX_CHANNELS);
+}
+
+#[test]
+fn test_spatial() {
+    let l = Listener { pos: Vec2::new(0.5, 0.5), half_width: 0.1 };
+    let (g_near, p_near) = spatialize(&l, Vec2::new(0.5, 0.52));
+    let (g_far, _) = spatialize(&l, Vec2::new(0.5, 0.8));
+    assert_eq!(g_near, 1.0);
+    assert_eq!(p_near, 0.0);
+    assert!(g_far < 0.05);
+    assert_eq!(spatialize(&l, Vec2::new(0.3, 0.5)).1, -1.0);
+    assert!(spatialize(&l, Vec2::new(0.55, 0.5)).1 > 0.4);
+
+    // behind a wall the highs go but the lows mostly stay
+    let rms = |f: f32, occluded: bool| {
+        let sd = SoundDesc { f, ..test_desc(0.0, 0.0, 1.0, 0.0, false) };
+        let out = render_offline(&[(0.0, SoundCommand::new(sd, 1).at(Vec2::zero(), occluded))], 0.2, OFFLINE_SAMPLE_RATE);
+        (out.iter().map(|x| x * x).sum::<f32>() / out.len() as f32).sqrt()
+    };
+    assert!(rms(4000.0, true) < 0.2 * rms(4000.0, false));
+    assert!(rms(100.0, true) > 0.5 * rms(100.0, false));
+}
+
+#[test]
+fn test_recording_backend() {
+    let mut audio = Audio::recording();
+    let sd = test_desc(0.0, 0.1, 0.5, 0.1, true);
+    audio.set_listener(Listener::default());
+    audio.handle_command(SoundCommand::new(sd, 3));
+    audio.set_bus(BusConfig::for_floor(2));
+    audio.handle_command(SoundCommand::new(sd, 4).at(Vec2::new(0.1, 0.2), true));
+
+    assert_eq!(audio.recorded().len(), 4);
+    assert!(matches!(audio.recorded()[0], AudioMessage::Listener(_)));
+    let sounds = audio.recorded_sounds();
+    assert_eq!(sounds.iter().map(|sc| sc.id).collect::<Vec<_>>(), vec![3, 4]);
+    assert!(sounds[1].occluded);
+
+    // null drops everything
+    let mut audio = Audio::with_backend(Box::new(NullBackend));
+    audio.handle_command(SoundCommand::new(sd, 3));
+    assert!(audio.recorded().is_empty());
+}
+
+#[test]
+fn test_sample_voices() {
+    // a ramp at 100hz played into a 200hz mixer
+    let smp = Arc::new(Sample { sample_rate: 100.0, data: (0..100).map(|i| i as f32 / 100.0).collect() });
+    let sd = SoundDesc { sample: 2, amp: 0.0, ..test_desc(0.0, 0.0, 1.0, 0.0, true) };
+
+    let mut m = Mixer::new(200.0);
+    m.load_sample(2, smp.clone());
+    m.handle_command(SoundCommand::new(sd, 1));
+    let out: Vec<f32> = (0..300).map(|_| m.tick().0).collect();
+    // plays every sample twice (interpolated), ends when the sample does rather than on the envelope
+    assert!((out[10] - 0.05).abs() < 1e-5);
+    assert!((out[11] - 0.055).abs() < 1e-5);
+    assert!(m.channels.is_empty());
+    assert!(out[201..].iter().all(|x| *x == 0.0));
+
+    // an octave up is half as long
+    let mut m = Mixer::new(200.0);
+    m.load_sample(2, smp.clone());
+    m.handle_command(SoundCommand::new(SoundDesc { pitch: 2.0, ..sd }, 1));
+    let n = (0..300).take_while(|_| { m.tick(); !m.channels.is_empty() }).count();
+    assert!((n as i32 - 100).abs() <= 1, "{}", n);
+
+    // loops while held, stops looping once released
+    let looped = SoundDesc { loop_start: 0.2, loop_end: 0.4, release: false, er: 0.05, ..sd };
+    let mut m = Mixer::new(200.0);
+    m.load_sample(2, smp.clone());
+    m.handle_command(SoundCommand::new(looped, 1));
+    let out: Vec<f32> = (0..1000).map(|_| m.tick().0).collect();
+    assert!(out[500..].iter().all(|x| *x >= 0.2 - 1e-5 && *x < 0.4));
+    m.handle_command(SoundCommand::new(SoundDesc { release: true, ..looped }, 1));
+    for _ in 0..20 {
+        m.tick();
+    }
+    assert!(m.channels.is_empty());
+
+    // pitch variation changes the rate per trigger but stays in range
+    let mut m = Mixer::new(200.0);
+    let varied = SoundDesc { pitch_var: 12.0, ..sd };
+    let rates: Vec<f32> = (0..20).map(|i| { m.tick(); m.trigger_rate(&varied, i) }).collect();
+    assert!(rates.iter().all(|r| *r >= 0.5 && *r <= 2.0));
+    assert!(rates.iter().any(|r| (r - rates[0]).abs() > 0.01));
+
+    // missing samples are just silent
+    let mut m = Mixer::new(200.0);
+    m.handle_command(SoundCommand::new(sd, 1));
+    assert_eq!(m.tick().0, 0.0);
+    assert!(m.channels.is_empty());
+}
+
+#[test]
+fn test_voice_stealing() {
+    let sd = test_desc(0.0, 0.0, 1.0, 1.0, false);
+    let mut m = Mixer::new(1000.0);
+    m.steal_policy = StealPolicy::Oldest;
+    // fill the pool with priority 2, the very first is the oldest
+    for id in 0..MAX_CHANNELS as u32 {
+        m.handle_command(SoundCommand::new(SoundDesc { priority: 2, ..sd }, id));
+        m.tick();
+    }
+    // lower priority cant get in
+    m.handle_command(SoundCommand::new(SoundDesc { priority: 1, ..sd }, 500));
+    assert!(!m.channels.iter().any(|ch| ch.id == 500));
+    // equal priority takes the oldest
+    m.handle_command(SoundCommand::new(SoundDesc { priority: 2, ..sd }, 501));
+    assert!(m.channels.iter().any(|ch| ch.id == 501));
+    assert!(!m.channels.iter().any(|ch| ch.id == 0));
+    assert_eq!(m.channels.len(), MAX_CHANNELS);
+
+    // quietest goes first, here the one at -40db
+    let mut m = Mixer::new(1000.0);
+    for id in 0..MAX_CHANNELS as u32 {
+        let amp = if id == 17 { -40.0 } else { -6.0 };
+        m.handle_command(SoundCommand::new(SoundDesc { amp, ..sd }, id));
+    }
+    m.tick();
+    m.handle_command(SoundCommand::new(sd, 600));
+    assert!(m.channels.iter().any(|ch| ch.id == 600));
+    assert!(!m.channels.iter().any(|ch| ch.id == 17));
+
+    // instance cap: a swarm of deaths never has more than 3 playing, the newest ones win
+    let mut m = Mixer::new(1000.0);
+    let death = SoundDesc { group: 4, max_instances: 3, ..sd };
+    for id in 0..20 {
+        m.handle_command(SoundCommand::new(death, id));
+        m.tick();
+    }
+    let ids: Vec<u32> = m.channels.iter().map(|ch| ch.id).collect();
+    assert_eq!(ids.len(), 3);
+    assert!(ids.iter().all(|id| *id >= 17));
+}
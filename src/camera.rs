@@ -0,0 +1,160 @@
+use crate::kmath::*;
+
+// world is the unit square, screen is (0, 0, aspect, 1) same as the canvas
+// the view is zoom tall and zoom * aspect wide, centred on pos plus whatever the shake is doing
+// follow is a critically damped spring towards the target so it eases in without overshooting
+// shake is trauma based: hits add trauma, trauma decays linearly, the offset goes with trauma squared so small hits are subtle
+
+const FOLLOW_OMEGA: f32 = 10.0;         // spring stiffness, higher is snappier
+const FOLLOW_MAX_STEP: f32 = 1.0 / 60.0;
+const LOOK_AHEAD: f32 = 0.2;            // how far towards the mouse the camera leans
+const ZOOM_MIN: f32 = 0.08;
+const ZOOM_MAX: f32 = 0.3;
+const ZOOM_STEP: f32 = 0.9;             // per scroll notch
+const ZOOM_RATE: f32 = 12.0;
+const TRAUMA_DECAY: f32 = 1.5;          // per second
+const SHAKE_MAX_OFFSET: f32 = 0.03;     // fraction of the view height
+const SHAKE_FREQ: f32 = 25.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub pos: Vec2,
+    vel: Vec2,
+    pub zoom: f32,
+    pub target_zoom: f32,
+    pub aspect: f32,
+    pub trauma: f32,
+    shake: Vec2,
+    t: f32,
+    seed: u32,
+}
+
+impl Camera {
+    pub fn new(pos: Vec2, zoom: f32, seed: u32) -> Camera {
+        Camera {
+            pos,
+            vel: Vec2::zero(),
+            zoom,
+            target_zoom: zoom,
+            aspect: 1.0,
+            trauma: 0.0,
+            shake: Vec2::zero(),
+            t: 0.0,
+            seed,
+        }
+    }
+
+    // for teleports like taking the stairs, dont swoop across the level
+    pub fn snap(&mut self, pos: Vec2) {
+        self.pos = pos;
+        self.vel = Vec2::zero();
+    }
+
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).min(1.0);
+    }
+
+    pub fn zoom_by(&mut self, scroll_delta: f32) {
+        self.target_zoom = (self.target_zoom * ZOOM_STEP.powf(scroll_delta)).clamp(ZOOM_MIN, ZOOM_MAX);
+    }
+
+    pub fn update(&mut self, dt: f32, follow: Vec2, look_at: Vec2, aspect: f32) {
+        self.aspect = aspect;
+        self.t += dt;
+
+        let target = follow.lerp(look_at, LOOK_AHEAD);
+        let mut remaining = dt;
+        while remaining > 0.0 {
+            let step = remaining.min(FOLLOW_MAX_STEP);
+            let acc = FOLLOW_OMEGA * FOLLOW_OMEGA * (target - self.pos) - 2.0 * FOLLOW_OMEGA * self.vel;
+            self.vel = self.vel + step * acc;
+            self.pos = self.pos + step * self.vel;
+            remaining -= step;
+        }
+
+        self.zoom += (self.target_zoom - self.zoom) * (1.0 - (-dt * ZOOM_RATE).exp());
+
+        self.trauma = (self.trauma - dt * TRAUMA_DECAY).max(0.0);
+        let amount = self.trauma * self.trauma * SHAKE_MAX_OFFSET * self.zoom;
+        self.shake = if amount > 0.0 {
+            Vec2::new(
+                noise1d(self.t * SHAKE_FREQ, self.seed) - 0.5,
+                noise1d(self.t * SHAKE_FREQ, khash(self.seed)) - 0.5,
+            ) * (2.0 * amount)
+        } else {
+            Vec2::zero()
+        };
+    }
+
+    // the part of the world on screen
+    pub fn view(&self) -> Rect {
+        let c = self.pos + self.shake;
+        Rect::new_centered(c.x, c.y, self.zoom * self.aspect, self.zoom)
+    }
+
+    pub fn world_to_screen(&self, p: Vec2) -> Vec2 {
+        let v = self.view();
+        Vec2::new((p.x - v.x) / v.h, (p.y - v.y) / v.h)
+    }
+
+    pub fn screen_to_world(&self, p: Vec2) -> Vec2 {
+        let v = self.view();
+        Vec2::new(v.x + p.x * v.h, v.y + p.y * v.h)
+    }
+
+    pub fn world_rect_to_screen(&self, r: Rect) -> Rect {
+        let tl = self.world_to_screen(r.tl());
+        Rect::new(tl.x, tl.y, self.world_len_to_screen(r.w), self.world_len_to_screen(r.h))
+    }
+
+    pub fn world_len_to_screen(&self, d: f32) -> f32 {
+        d / self.zoom
+    }
+}
+
+#[test]
+fn test_camera() {
+    let mut cam = Camera::new(Vec2::new(0.5, 0.5), 0.15, 1);
+    cam.update(0.0, Vec2::new(0.5, 0.5), Vec2::new(0.5, 0.5), 1.5);
+
+    // transforms go both ways and the view fills the screen
+    let p = Vec2::new(0.52, 0.47);
+    assert!(cam.screen_to_world(cam.world_to_screen(p)).dist(p) < 1e-6);
+    let sr = cam.world_rect_to_screen(cam.view());
+    assert!(sr.x.abs() < 1e-5 && sr.y.abs() < 1e-5 && (sr.w - 1.5).abs() < 1e-5 && (sr.h - 1.0).abs() < 1e-5);
+    assert!(cam.world_to_screen(Vec2::new(0.5, 0.5)).dist(Vec2::new(0.75, 0.5)) < 1e-5);
+
+    // follows smoothly without overshooting, leaning towards the mouse
+    let target = Vec2::new(0.6, 0.5);
+    let look = Vec2::new(0.7, 0.5);
+    let mut last_x = cam.pos.x;
+    for _ in 0..120 {
+        cam.update(1.0 / 60.0, target, look, 1.5);
+        assert!(cam.pos.x >= last_x && cam.pos.x <= 0.62 + 1e-4);
+        last_x = cam.pos.x;
+    }
+    assert!((cam.pos.x - 0.62).abs() < 0.001);
+
+    // zoom eases towards the scroll target and stays in its limits
+    cam.zoom_by(100.0);
+    assert_eq!(cam.target_zoom, ZOOM_MIN);
+    for _ in 0..60 {
+        cam.update(1.0 / 60.0, target, look, 1.5);
+    }
+    assert!((cam.zoom - ZOOM_MIN).abs() < 0.001);
+    cam.zoom_by(-100.0);
+    assert_eq!(cam.target_zoom, ZOOM_MAX);
+
+    // shake moves the view and then settles once trauma runs out
+    let still = cam.view();
+    cam.add_trauma(2.0);
+    assert_eq!(cam.trauma, 1.0);
+    cam.update(0.01, target, look, 1.5);
+    assert!(cam.view().centroid().dist(still.centroid()) > 0.0);
+    assert!(cam.view().centroid().dist(cam.pos) <= SHAKE_MAX_OFFSET * cam.zoom * 1.5);
+    for _ in 0..60 {
+        cam.update(1.0 / 60.0, target, look, 1.5);
+    }
+    assert_eq!(cam.trauma, 0.0);
+    assert_eq!(cam.view().centroid(), cam.pos);
+}
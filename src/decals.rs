@@ -0,0 +1,148 @@
+use crate::kmath::*;
+use crate::texture_buffer::*;
+
+// decals get painted straight into a cpu copy of the level texture, so they stay for the rest of the floor
+// each frame whatever changed goes up as one sub image rather than reuploading the whole 2000x2000 thing
+// the level is the unit square and row 0 of the buffer is the bottom of the level, same as when the level texture is made
+
+#[derive(Clone, Copy, Debug)]
+pub struct DecalDesc {
+    pub colour: Vec4,
+    pub alpha: f32,         // how much of the floor it covers at the centre
+    pub radius: f32,        // world units
+    pub roughness: f32,     // 0 is a clean circle, 1 is very ragged
+    pub softness: f32,      // fraction of the radius that fades out
+    pub drops: usize,       // little satellite splats
+    pub drop_spread: f32,   // how far out drops land, in radii
+}
+
+pub const BLOOD_SPLAT: DecalDesc = DecalDesc {
+    colour: Vec4::new(0.35, 0.02, 0.02, 1.0),
+    alpha: 0.8,
+    radius: 0.003,
+    roughness: 0.6,
+    softness: 0.3,
+    drops: 5,
+    drop_spread: 2.5,
+};
+
+pub const SCORCH: DecalDesc = DecalDesc {
+    colour: Vec4::new(0.05, 0.03, 0.02, 1.0),
+    alpha: 0.12,
+    radius: 0.0015,
+    roughness: 0.3,
+    softness: 0.8,
+    drops: 0,
+    drop_spread: 0.0,
+};
+
+// colour gets swapped for the enemies colour, darkened
+pub const CORPSE: DecalDesc = DecalDesc {
+    colour: Vec4::new(0.2, 0.2, 0.2, 1.0),
+    alpha: 0.9,
+    radius: 0.003,
+    roughness: 0.2,
+    softness: 0.15,
+    drops: 0,
+    drop_spread: 0.0,
+};
+
+pub struct DecalLayer {
+    pub tb: TextureBuffer,
+    dirty: Option<(usize, usize, usize, usize)>,    // x0, y0, x1, y1 in pixels, inclusive
+}
+
+impl DecalLayer {
+    pub fn new(tb: TextureBuffer) -> DecalLayer {
+        DecalLayer { tb, dirty: None }
+    }
+
+    pub fn stamp(&mut self, desc: &DecalDesc, pos: Vec2, seed: u32) {
+        self.splat(desc, pos, desc.radius, seed);
+        for k in 0..desc.drops {
+            let s = khash(seed.wrapping_add(k as u32 * 7919 + 1));
+            let theta = krand(s) * 2.0 * PI;
+            let r = desc.radius * desc.drop_spread * (0.5 + 0.5 * krand(khash(s)));
+            let size = desc.radius * (0.15 + 0.25 * krand(khash(s ^ 1234567)));
+            self.splat(desc, pos.offset_r_theta(r, theta), size, s);
+        }
+    }
+
+    fn splat(&mut self, desc: &DecalDesc, pos: Vec2, radius: f32, seed: u32) {
+        let (w, h) = (self.tb.w as f32, self.tb.h as f32);
+        let cx = pos.x * w;
+        let cy = h - pos.y * h;
+        let rp = radius * w * (1.0 + desc.roughness * 0.5);
+        let x0 = (cx - rp).floor().max(0.0) as usize;
+        let y0 = (cy - rp).floor().max(0.0) as usize;
+        let x1 = ((cx + rp).ceil().max(0.0) as usize).min(self.tb.w - 1);
+        let y1 = ((cy + rp).ceil().max(0.0) as usize).min(self.tb.h - 1);
+        if x0 > x1 || y0 > y1 {
+            return;
+        }
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let dx = x as f32 + 0.5 - cx;
+                let dy = y as f32 + 0.5 - cy;
+                // ragged edge: the radius wobbles with angle
+                let theta = dy.atan2(dx) + PI;
+                let edge = radius * w * (1.0 + desc.roughness * (noise1d(theta * 3.0, seed) - 0.5));
+                let d = (dx * dx + dy * dy).sqrt() / edge.max(0.5);
+                if d >= 1.0 {
+                    continue;
+                }
+                let fade = if desc.softness > 0.0 { ((1.0 - d) / desc.softness).min(1.0) } else { 1.0 };
+                let a = desc.alpha * fade;
+                let under = self.tb.get(x as i32, y as i32);
+                let c = under.lerp(desc.colour, a);
+                self.tb.set(x as i32, y as i32, Vec4::new(c.x, c.y, c.z, under.w));
+            }
+        }
+        self.dirty = Some(match self.dirty {
+            None => (x0, y0, x1, y1),
+            Some((a, b, c, d)) => (a.min(x0), b.min(y0), c.max(x1), d.max(y1)),
+        });
+    }
+
+    // the changed region since the last call and where it goes
+    pub fn take_dirty_patch(&mut self) -> Option<(TextureBuffer, usize, usize)> {
+        let (x0, y0, x1, y1) = self.dirty.take()?;
+        Some((self.tb.sub(x0, y0, x1 - x0 + 1, y1 - y0 + 1), x0, y0))
+    }
+}
+
+#[test]
+fn test_decals() {
+    let mut tb = TextureBuffer::new(200, 200);
+    for i in 0..200 {
+        for j in 0..200 {
+            tb.set(i, j, Vec4::new(0.5, 0.5, 0.5, 1.0));
+        }
+    }
+    let mut layer = DecalLayer::new(tb);
+    let grey = layer.tb.get(0, 0);
+    assert!(layer.take_dirty_patch().is_none());
+
+    // world (0.25, 0.25) is pixel (50, 150) because row 0 is the bottom
+    let desc = DecalDesc { radius: 0.02, drops: 0, roughness: 0.0, ..BLOOD_SPLAT };
+    layer.stamp(&desc, Vec2::new(0.25, 0.25), 1);
+    let centre = layer.tb.get(50, 150);
+    assert!(centre.x < 0.5 && centre.y < 0.2 && centre.w == 1.0);
+    assert_eq!(layer.tb.get(50, 50), grey);
+    assert_eq!(layer.tb.get(60, 150), grey);
+
+    // the patch is just around the splat and matches the layer
+    let (patch, x, y) = layer.take_dirty_patch().unwrap();
+    assert!(patch.w <= 12 && patch.h <= 12);
+    assert!(x <= 50 && x + patch.w > 50 && y <= 150 && y + patch.h > 150);
+    assert_eq!(patch.get(50 - x as i32, 150 - y as i32), centre);
+    assert!(layer.take_dirty_patch().is_none());
+
+    // stamping again darkens further, decals accumulate
+    layer.stamp(&desc, Vec2::new(0.25, 0.25), 2);
+    assert!(layer.tb.get(50, 150).x < centre.x);
+
+    // splats off the edge of the level just get clipped
+    layer.stamp(&BLOOD_SPLAT, Vec2::new(-0.001, 1.0), 3);
+    layer.stamp(&BLOOD_SPLAT, Vec2::new(5.0, 5.0), 3);
+}
@@ -0,0 +1,329 @@
+use crate::kmath::*;
+use crate::audio::db_to_vol;
+
+// filters and master bus effects for the mixer
+// everything that needs a buffer allocates it in new() so process() is safe on the audio thread
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterKind {
+    Off,
+    Low,
+    High,
+    Band,
+}
+
+// state variable filter, the trapezoidal (zero delay feedback) one so it stays stable when the cutoff moves every sample
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Svf {
+    ic1eq: f32,
+    ic2eq: f32,
+}
+
+impl Svf {
+    pub fn process(&mut self, x: f32, kind: FilterKind, cutoff: f32, q: f32, sample_rate: f32) -> f32 {
+        if kind == FilterKind::Off {
+            return x;
+        }
+        let cutoff = cutoff.clamp(10.0, sample_rate * 0.49);
+        let g = (PI * cutoff / sample_rate).tan();
+        let k = 1.0 / q.max(0.1);
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+
+        let v3 = x - self.ic2eq;
+        let v1 = a1 * self.ic1eq + a2 * v3;
+        let v2 = self.ic2eq + a2 * self.ic1eq + a3 * v3;
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+
+        match kind {
+            FilterKind::Low => v2,
+            FilterKind::Band => k * v1,   // unity gain at the centre whatever the resonance
+            FilterKind::High => x - k * v1 - v2,
+            FilterKind::Off => x,
+        }
+    }
+}
+
+// per floor settings for the master bus, mixes are 0 dry .. 1 wet
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BusConfig {
+    pub delay_time: f32,
+    pub delay_feedback: f32,
+    pub delay_mix: f32,
+    pub reverb_room: f32,   // 0..1, how long the tail is
+    pub reverb_damp: f32,   // 0..1, how fast the highs die in the tail
+    pub reverb_mix: f32,
+    pub limiter_threshold: f32,
+}
+
+impl Default for BusConfig {
+    fn default() -> Self {
+        BusConfig {
+            delay_time: 0.25,
+            delay_feedback: 0.0,
+            delay_mix: 0.0,
+            reverb_room: 0.5,
+            reverb_damp: 0.5,
+            reverb_mix: 0.0,
+            limiter_threshold: 1.0,
+        }
+    }
+}
+
+impl BusConfig {
+    // the deeper you go the bigger and wetter the caves sound
+    pub fn for_floor(floor: i32) -> BusConfig {
+        let depth = ((floor - 1).max(0) as f32 / 8.0).min(1.0);
+        BusConfig {
+            delay_time: lerp(0.18, 0.45, depth),
+            delay_feedback: lerp(0.15, 0.45, depth),
+            delay_mix: lerp(0.05, 0.2, depth),
+            reverb_room: lerp(0.5, 0.88, depth),
+            reverb_damp: lerp(0.6, 0.3, depth),
+            reverb_mix: lerp(0.12, 0.35, depth),
+            limiter_threshold: db_to_vol(-1.0),
+        }
+    }
+}
+
+pub const MAX_DELAY: f32 = 1.0;
+
+pub struct Delay {
+    buf: Vec<f32>,
+    idx: usize,
+}
+
+impl Delay {
+    pub fn new(max_samples: usize) -> Delay {
+        Delay { buf: vec![0.0; max_samples.max(1)], idx: 0 }
+    }
+
+    // feedback delay line, returns the wet signal
+    pub fn process(&mut self, x: f32, delay_samples: usize, feedback: f32) -> f32 {
+        let n = self.buf.len();
+        let d = delay_samples.clamp(1, n);
+        let out = self.buf[(self.idx + n - d) % n];
+        self.buf[self.idx] = x + out * feedback;
+        self.idx = (self.idx + 1) % n;
+        out
+    }
+}
+
+struct Comb {
+    buf: Vec<f32>,
+    idx: usize,
+    lp: f32,
+}
+
+impl Comb {
+    fn new(len: usize) -> Comb {
+        Comb { buf: vec![0.0; len.max(1)], idx: 0, lp: 0.0 }
+    }
+
+    fn process(&mut self, x: f32, feedback: f32, damp: f32) -> f32 {
+        let out = self.buf[self.idx];
+        self.lp = out * (1.0 - damp) + self.lp * damp;
+        self.buf[self.idx] = x + self.lp * feedback;
+        self.idx = (self.idx + 1) % self.buf.len();
+        out
+    }
+}
+
+struct Allpass {
+    buf: Vec<f32>,
+    idx: usize,
+}
+
+impl Allpass {
+    fn new(len: usize) -> Allpass {
+        Allpass { buf: vec![0.0; len.max(1)], idx: 0 }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let b = self.buf[self.idx];
+        let out = b - x;
+        self.buf[self.idx] = x + b * 0.5;
+        self.idx = (self.idx + 1) % self.buf.len();
+        out
+    }
+}
+
+// freeverb style: parallel damped combs into series allpasses, right side slightly longer for width
+// tunings are in samples at 44.1k
+const COMB_TUNING: [usize; 4] = [1116, 1277, 1422, 1557];
+const ALLPASS_TUNING: [usize; 2] = [556, 441];
+const STEREO_SPREAD: usize = 23;
+
+pub struct Reverb {
+    combs: [Vec<Comb>; 2],
+    allpasses: [Vec<Allpass>; 2],
+}
+
+impl Reverb {
+    pub fn new(sample_rate: f32) -> Reverb {
+        let scale = |n: usize| (n as f32 * sample_rate / 44100.0) as usize;
+        let side = |spread: usize| -> (Vec<Comb>, Vec<Allpass>) {
+            (
+                COMB_TUNING.iter().map(|n| Comb::new(scale(n + spread))).collect(),
+                ALLPASS_TUNING.iter().map(|n| Allpass::new(scale(n + spread))).collect(),
+            )
+        };
+        let (cl, al) = side(0);
+        let (cr, ar) = side(STEREO_SPREAD);
+        Reverb { combs: [cl, cr], allpasses: [al, ar] }
+    }
+
+    pub fn process(&mut self, x: (f32, f32), room: f32, damp: f32) -> (f32, f32) {
+        let input = (x.0 + x.1) * 0.1;
+        let feedback = lerp(0.7, 0.98, room.clamp(0.0, 1.0));
+        let damp = damp.clamp(0.0, 1.0) * 0.4;
+        let mut out = [0.0; 2];
+        for side in 0..2 {
+            let mut acc = 0.0;
+            for c in self.combs[side].iter_mut() {
+                acc += c.process(input, feedback, damp);
+            }
+            for a in self.allpasses[side].iter_mut() {
+                acc = a.process(acc);
+            }
+            out[side] = acc;
+        }
+        (out[0], out[1])
+    }
+}
+
+// stereo linked peak limiter, instant attack and a short release, then a hard clip as a last resort
+pub struct Limiter {
+    gain: f32,
+    release_k: f32,
+}
+
+impl Limiter {
+    pub fn new(sample_rate: f32) -> Limiter {
+        let release_secs = 0.1;
+        Limiter { gain: 1.0, release_k: 1.0 - (-1.0 / (release_secs * sample_rate)).exp() }
+    }
+
+    pub fn process(&mut self, x: (f32, f32), threshold: f32) -> (f32, f32) {
+        let peak = x.0.abs().max(x.1.abs());
+        let needed = if peak > threshold { threshold / peak } else { 1.0 };
+        if needed < self.gain {
+            self.gain = needed;
+        } else {
+            self.gain += (needed - self.gain) * self.release_k;
+        }
+        ((x.0 * self.gain).clamp(-threshold, threshold), (x.1 * self.gain).clamp(-threshold, threshold))
+    }
+}
+
+pub struct MasterBus {
+    pub config: BusConfig,
+    sample_rate: f32,
+    delay: [Delay; 2],
+    reverb: Reverb,
+    limiter: Limiter,
+}
+
+impl MasterBus {
+    pub fn new(sample_rate: f32) -> MasterBus {
+        let max_delay = (MAX_DELAY * sample_rate) as usize;
+        MasterBus {
+            config: BusConfig::default(),
+            sample_rate,
+            delay: [Delay::new(max_delay), Delay::new(max_delay)],
+            reverb: Reverb::new(sample_rate),
+            limiter: Limiter::new(sample_rate),
+        }
+    }
+
+    pub fn process(&mut self, x: (f32, f32)) -> (f32, f32) {
+        let c = self.config;
+        let mut out = x;
+        if c.delay_mix > 0.0 {
+            let d = (c.delay_time.min(MAX_DELAY) * self.sample_rate) as usize;
+            out.0 += c.delay_mix * self.delay[0].process(x.0, d, c.delay_feedback);
+            out.1 += c.delay_mix * self.delay[1].process(x.1, d, c.delay_feedback);
+        }
+        if c.reverb_mix > 0.0 {
+            let (rl, rr) = self.reverb.process(out, c.reverb_room, c.reverb_damp);
+            out.0 += c.reverb_mix * rl;
+            out.1 += c.reverb_mix * rr;
+        }
+        self.limiter.process(out, c.limiter_threshold)
+    }
+}
+
+#[cfg(test)]
+fn sine_rms(f: f32, kind: FilterKind, cutoff: f32) -> f32 {
+    let sr = 44100.0;
+    let mut svf = Svf::default();
+    let mut acc = 0.0;
+    let n = 8820;
+    for i in 0..n {
+        let y = svf.process((2.0 * PI * f * i as f32 / sr).sin(), kind, cutoff, 0.707, sr);
+        // skip the settling
+        if i > n / 2 {
+            acc += y * y;
+        }
+    }
+    (acc / (n / 2) as f32).sqrt()
+}
+
+#[test]
+fn test_svf() {
+    let unfiltered = sine_rms(100.0, FilterKind::Off, 1000.0);
+    assert!((unfiltered - 0.707).abs() < 0.01);
+
+    assert!(sine_rms(100.0, FilterKind::Low, 1000.0) > 0.95 * unfiltered);
+    assert!(sine_rms(8000.0, FilterKind::Low, 1000.0) < 0.05 * unfiltered);
+
+    assert!(sine_rms(100.0, FilterKind::High, 1000.0) < 0.05 * unfiltered);
+    assert!(sine_rms(8000.0, FilterKind::High, 1000.0) > 0.95 * unfiltered);
+
+    let centre = sine_rms(1000.0, FilterKind::Band, 1000.0);
+    assert!(centre > 0.9 * unfiltered);
+    assert!(sine_rms(100.0, FilterKind::Band, 1000.0) < 0.2 * centre);
+    assert!(sine_rms(10000.0, FilterKind::Band, 1000.0) < 0.2 * centre);
+}
+
+#[test]
+fn test_master_bus() {
+    let sr = 1000.0;
+
+    // dry default passes straight through
+    let mut bus = MasterBus::new(sr);
+    assert_eq!(bus.process((0.3, -0.2)), (0.3, -0.2));
+
+    // echo lands exactly delay_time later and decays by the feedback
+    let mut bus = MasterBus::new(sr);
+    bus.config = BusConfig { delay_time: 0.1, delay_feedback: 0.5, delay_mix: 1.0, ..Default::default() };
+    let out: Vec<f32> = (0..301).map(|i| bus.process((if i == 0 { 0.5 } else { 0.0 }, 0.0)).0).collect();
+    assert_eq!(out[0], 0.5);
+    assert!(out[1..100].iter().all(|x| *x == 0.0));
+    assert_eq!(out[100], 0.5);
+    assert_eq!(out[200], 0.25);
+    assert_eq!(out[300], 0.125);
+
+    // limiter never lets anything over the threshold through
+    let mut bus = MasterBus::new(sr);
+    bus.config.limiter_threshold = 0.5;
+    for i in 0..1000 {
+        let x = 2.0 * (i as f32 * 0.37).sin();
+        let (l, r) = bus.process((x, -x));
+        assert!(l.abs() <= 0.5 && r.abs() <= 0.5);
+    }
+
+    // reverb rings on after an impulse and dies away
+    let sr = 44100.0;
+    let mut bus = MasterBus::new(sr);
+    bus.config = BusConfig::for_floor(8);
+    bus.config.delay_mix = 0.0;
+    let out: Vec<f32> = (0..(sr as usize * 4)).map(|i| bus.process(if i == 0 { (1.0, 1.0) } else { (0.0, 0.0) }).0).collect();
+    let energy = |s: &[f32]| s.iter().map(|x| x * x).sum::<f32>();
+    let early = energy(&out[4410..22050]);
+    let late = energy(&out[sr as usize * 3..]);
+    assert!(early > 1e-4);
+    assert!(late < early * 0.1);
+}
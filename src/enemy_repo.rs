@@ -1,4 +1,5 @@
 use crate::kmath::*;
+use crate::enemy_shape::*;
 
 #[derive(Clone)]
 pub struct PackRecord {
@@ -26,6 +27,7 @@ pub struct EnemyRecord {
 
     pub colour_inner: Vec4,
     pub colour_outer: Vec4,
+    pub shape: EnemyShape,
 }
 
 impl Default for EnemyRecord {
@@ -49,6 +51,7 @@ impl Default for EnemyRecord {
 
             colour_inner: Vec4::new(1.0, 1.0, 1.0, 1.0),
             colour_outer: Vec4::new(0.0, 0.0, 0.0, 1.0),
+            shape: SHAPE_BLOB,
         }
     }
 }
@@ -56,6 +59,7 @@ impl Default for EnemyRecord {
 pub struct EnemyRepo {
     pub enemies: Vec<EnemyRecord>,
     pub spawn_table: Vec<Vec<(usize, usize)>>,
+    pub shade: usize,   // comes out of the dark when the lamp runs dry
 }
 
 impl EnemyRepo {
@@ -73,6 +77,7 @@ impl Default for EnemyRepo {
         let mut repo = EnemyRepo { 
             enemies: Vec::new(),
             spawn_table: Vec::new(),
+            shade: 0,
         };
         let mut default = EnemyRecord::default();
 
@@ -80,6 +85,7 @@ impl Default for EnemyRepo {
         basic.speed_to_target = 0.02;
         basic.speed_wander = 0.04;
         basic.colour_inner = Vec4::new(0.5, 0.1, 0.1, 1.0);
+        basic.shape.eyes = 2;
         let basic_id = repo.push(basic);
 
         let mut fast_green_projectile = default;
@@ -88,6 +94,7 @@ impl Default for EnemyRepo {
         fast_green_projectile.colour_inner = Vec4::new(0.2, 0.5, 0.0, 1.0);
         fast_green_projectile.colour_outer = Vec4::new(1., 1., 1., 1.);
         fast_green_projectile.initial_hp = 0.1;
+        fast_green_projectile.shape = EnemyShape { spikes: 6, spike_len: 0.3, facing: Facing::Roll(200.0), squash: 0.0, ..SHAPE_BLOB };
         let fast_green_projectile_id = repo.push(fast_green_projectile);
         
         let mut slow_green_projectile = fast_green_projectile;
@@ -101,6 +108,7 @@ impl Default for EnemyRepo {
         shooter.projectile_cooldown = 1.0;
         shooter.colour_inner = Vec4::new(0.2, 0.5, 0.0, 1.0);
         shooter.initial_hp = 0.4;
+        shooter.shape = EnemyShape { sides: 5, eyes: 1, eye_size: 0.35, ..SHAPE_BLOB };
         let shooter_id = repo.push(shooter);
 
         let mut stationary_shooter = shooter;
@@ -110,6 +118,7 @@ impl Default for EnemyRepo {
         stationary_shooter.speed_to_target = 0.0;
         stationary_shooter.initial_hp = 2.0;
         stationary_shooter.shoot_range = 0.15;
+        stationary_shooter.shape = EnemyShape { sides: 6, spikes: 6, spike_len: 0.25, eyes: 1, eye_size: 0.3, squash: 0.1, ..SHAPE_BLOB };
         repo.push(stationary_shooter);
         let stationary_shooter_id = repo.push(stationary_shooter);
 
@@ -127,6 +136,7 @@ impl Default for EnemyRepo {
         locust.radius = 0.0025;
         locust.projectile_cooldown = 1.3;
         locust.acquisition_radius = 0.2;
+        locust.shape = EnemyShape { sides: 3, eyes: 2, eye_size: 0.22, facing: Facing::Velocity, ..SHAPE_BLOB };
         let locust_id = repo.push(locust);
 
         let mut swarm_host = locust;
@@ -135,6 +145,7 @@ impl Default for EnemyRepo {
         swarm_host.speed_to_target = 0.01;
         swarm_host.projectile = locust_id as i32;
         swarm_host.shoot_range = 0.2;
+        swarm_host.shape = EnemyShape { sides: 8, spikes: 8, spike_len: 0.2, eyes: 3, eye_size: 0.2, facing: Facing::Player, squash: 0.15, ..SHAPE_BLOB };
         let swarm_host_id = repo.push(swarm_host);
 
         let mut rusher = default;
@@ -143,12 +154,14 @@ impl Default for EnemyRepo {
         rusher.speed_to_target = 0.07;
         rusher.melee_damage = 0.3;
         rusher.colour_inner = Vec4::new(0.5, 0.0, 0.0, 1.0);
+        rusher.shape = EnemyShape { sides: 3, spikes: 3, spike_len: 0.5, spike_width: 0.3, eyes: 2, eye_size: 0.22, facing: Facing::Velocity, ..SHAPE_BLOB };
         let rusher_id = repo.push(rusher);
 
         let mut easy_guy = basic;
         easy_guy.initial_hp = 0.5;
         easy_guy.colour_inner = Vec4::new(0.4, 0.0, 0.4, 1.0);
         easy_guy.speed_wander = 0.01;
+        easy_guy.shape = EnemyShape { eyes: 2, eye_size: 0.32, ..SHAPE_BLOB };
         let easy_guy_id = repo.push(easy_guy);
 
         let mut easy_bullet = slow_green_projectile;
@@ -165,11 +178,13 @@ impl Default for EnemyRepo {
         easy_shooter.shoot_range = 0.1;
         easy_shooter.acquisition_radius = 0.03;
         easy_shooter.projectile_cooldown = 2.5;
+        easy_shooter.shape.sides = 4;
         let easy_shooter_id = repo.push(easy_shooter);
 
         let mut death_missile = easy_bullet;
         death_missile.colour_inner = Vec4::grey(0.1);
         death_missile.speed_to_target = 0.03;
+        death_missile.shape = EnemyShape { spikes: 4, spike_len: 0.6, facing: Facing::Roll(300.0), squash: 0.0, ..SHAPE_BLOB };
         let death_missile_id = repo.push(death_missile);
 
         let mut deathcaster = easy_shooter;
@@ -180,8 +195,20 @@ impl Default for EnemyRepo {
         deathcaster.radius = 0.005;
         deathcaster.initial_hp = 2.0;
         deathcaster.projectile = death_missile_id as i32;
+        deathcaster.shape = EnemyShape { sides: 6, spikes: 6, spike_len: 0.7, spike_width: 0.3, eyes: 1, eye_size: 0.3, eye_colour: Vec4::new(0.9, 0.1, 0.1, 1.0), ..SHAPE_BLOB };
         let deathcaster_id = repo.push(deathcaster);
 
+        let mut shade = default;
+        shade.initial_hp = 0.3;
+        shade.radius = 0.0035;
+        shade.speed_to_target = 0.05;
+        shade.acquisition_radius = 0.3;
+        shade.melee_damage = 0.2;
+        shade.colour_inner = Vec4::grey(0.02);
+        shade.colour_outer = Vec4::new(0.25, 0.0, 0.35, 1.0);
+        shade.shape = EnemyShape { spikes: 12, spike_len: 0.5, spike_width: 0.4, eyes: 2, eye_size: 0.2, eye_colour: Vec4::new(0.7, 0.4, 1.0, 1.0), facing: Facing::Velocity, ..SHAPE_BLOB };
+        repo.shade = repo.push(shade);
+
         let easy_pack = vec![(easy_guy_id, 6)];
         let easy_shooter_pack = vec![(easy_shooter_id, 6)];
         let easy_mixed_pack = vec![(easy_shooter_id, 3), (easy_guy_id, 3)];
@@ -0,0 +1,175 @@
+use crate::kmath::*;
+use crate::renderers::simple_renderer::*;
+
+// declarative look for an enemy, lives on the EnemyRecord next to the colours
+// the body is an n sided polygon (0 is a circle) in colour_inner with a colour_outer rim, spikes stick out of the rim,
+// eyes sit at the front of the body and the pupils follow the player
+// hits squash the body along its facing and flash it towards white, everything is plain triangles on the canvas
+
+const SQUASH_TIME: f32 = 0.25;
+const SQUASH_FREQ: f32 = 40.0;
+const FLASH_TIME: f32 = 0.12;
+const TURN_RATE: f32 = 8.0;
+const RIM: f32 = 1.2;           // outer ring, in radii
+
+// continuous damage restarts the hit animation this often rather than every frame
+pub const HIT_PULSE: f32 = 0.3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Facing {
+    Player,
+    Velocity,
+    Roll(f32),      // radians per world unit travelled
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct EnemyShape {
+    pub sides: i32,         // 0 is a circle
+    pub spikes: i32,
+    pub spike_len: f32,     // past the rim, in radii
+    pub spike_width: f32,   // fraction of the gap between spikes
+    pub eyes: i32,
+    pub eye_size: f32,      // in radii
+    pub eye_colour: Vec4,
+    pub facing: Facing,
+    pub squash: f32,        // 0..1, how much a hit flattens it
+    pub flash: f32,         // 0..1, how white a hit makes it
+}
+
+// two concentric circles, what every enemy used to be
+pub const SHAPE_BLOB: EnemyShape = EnemyShape {
+    sides: 0,
+    spikes: 0,
+    spike_len: 0.4,
+    spike_width: 0.5,
+    eyes: 0,
+    eye_size: 0.28,
+    eye_colour: Vec4::new(1.0, 1.0, 1.0, 1.0),
+    facing: Facing::Player,
+    squash: 0.25,
+    flash: 0.8,
+};
+
+impl Default for EnemyShape {
+    fn default() -> Self {
+        SHAPE_BLOB
+    }
+}
+
+// where and how its drawn this frame, pos and radius are screen space
+#[derive(Clone, Copy, Debug)]
+pub struct EnemyPose {
+    pub pos: Vec2,
+    pub radius: f32,
+    pub angle: f32,
+    pub look: Vec2,     // unit vector towards whatever the eyes watch
+    pub hit_age: f32,   // seconds since last hit
+}
+
+impl EnemyShape {
+    // v is the enemies velocity and to_player points at the player, both world space
+    pub fn update_angle(&self, angle: f32, v: Vec2, to_player: Vec2, dt: f32) -> f32 {
+        let target = match self.facing {
+            Facing::Roll(rate) => return angle + rate * v.magnitude() * dt,
+            Facing::Velocity if v.magnitude() > 0.0 => v.y.atan2(v.x),
+            Facing::Player if to_player.magnitude() > 0.0 => to_player.y.atan2(to_player.x),
+            _ => return angle,
+        };
+        let mut diff = (target - angle) % (2.0 * PI);
+        if diff > PI { diff -= 2.0 * PI; }
+        if diff < -PI { diff += 2.0 * PI; }
+        angle + diff * (1.0 - (-dt * TURN_RATE).exp())
+    }
+
+    pub fn draw(&self, canvas: &mut SimpleCanvas, pose: &EnemyPose, inner: Vec4, outer: Vec4, depth: f32) {
+        let squash_env = (1.0 - pose.hit_age / SQUASH_TIME).max(0.0);
+        let k = self.squash * squash_env * (pose.hit_age * SQUASH_FREQ).cos();
+        let flash = self.flash * (1.0 - pose.hit_age / FLASH_TIME).max(0.0);
+        let white = Vec4::new(1.0, 1.0, 1.0, 1.0);
+        let inner = inner.lerp(Vec4 { w: inner.w, ..white }, flash);
+        let outer = outer.lerp(Vec4 { w: outer.w, ..white }, flash);
+
+        // local space is in radii with +x the way its facing
+        let xf = |v: Vec2| pose.pos + Vec2::new(v.x * (1.0 - k), v.y * (1.0 + k)).rotate(pose.angle) * pose.radius;
+
+        let n = if self.sides > 0 { self.sides } else { ((pose.radius * RIM).sqrt() * 200.0).max(6.0) as i32 };
+        let body = |r: f32, depth: f32, colour: Vec4, canvas: &mut SimpleCanvas| {
+            for i in 0..n {
+                let t1 = i as f32 * 2.0 * PI / n as f32;
+                let t2 = (i + 1) as f32 * 2.0 * PI / n as f32;
+                canvas.put_triangle(pose.pos, xf(Vec2::new_r_theta(r, t1)), xf(Vec2::new_r_theta(r, t2)), depth, colour);
+            }
+        };
+        body(RIM, depth, outer, canvas);
+        body(1.0, depth + 0.1, inner, canvas);
+
+        for i in 0..self.spikes {
+            let theta = i as f32 * 2.0 * PI / self.spikes as f32;
+            let half = PI / self.spikes as f32 * self.spike_width;
+            canvas.put_triangle(
+                xf(Vec2::new_r_theta(1.0, theta - half)),
+                xf(Vec2::new_r_theta(RIM + self.spike_len, theta)),
+                xf(Vec2::new_r_theta(1.0, theta + half)),
+                depth, outer);
+        }
+
+        let eye_r = self.eye_size * pose.radius;
+        for i in 0..self.eyes {
+            let y = if self.eyes == 1 { 0.0 } else { lerp(-0.4, 0.4, i as f32 / (self.eyes - 1) as f32) };
+            let centre = xf(Vec2::new(0.35, y));
+            canvas.put_poly(centre, eye_r, 8, depth + 0.12, self.eye_colour);
+            canvas.put_poly(centre + pose.look * (eye_r * 0.45), eye_r * 0.5, 6, depth + 0.14, Vec4::grey(0.0));
+        }
+    }
+}
+
+#[cfg(test)]
+fn triangle_colour(canvas: &SimpleCanvas, tri: usize) -> Vec4 {
+    let f = |k: usize| {
+        let o = (tri * 3 * 7 + k) * 4;
+        f32::from_le_bytes([canvas.buf[o], canvas.buf[o + 1], canvas.buf[o + 2], canvas.buf[o + 3]])
+    };
+    Vec4::new(f(3), f(4), f(5), f(6))
+}
+
+#[test]
+fn test_enemy_shape() {
+    let tris = |c: &SimpleCanvas| c.buf.len() / (3 * 7 * 4);
+    let pose = EnemyPose { pos: Vec2::new(0.5, 0.5), radius: 0.02, angle: 0.0, look: Vec2::new(1.0, 0.0), hit_age: 100.0 };
+    let inner = Vec4::new(0.5, 0.1, 0.1, 1.0);
+    let outer = Vec4::new(0.0, 0.0, 0.0, 1.0);
+
+    // pentagon with 5 spikes and 2 eyes: two bodies, a triangle per spike, sclera and pupil per eye
+    let shape = EnemyShape { sides: 5, spikes: 5, eyes: 2, ..SHAPE_BLOB };
+    let mut canvas = SimpleCanvas::new(1.0);
+    shape.draw(&mut canvas, &pose, inner, outer, 1.5);
+    assert_eq!(tris(&canvas), 5 + 5 + 5 + 2 * (8 + 6));
+    assert_eq!(triangle_colour(&canvas, 5), inner);
+
+    // the blob is still just two circles
+    let mut canvas = SimpleCanvas::new(1.0);
+    SHAPE_BLOB.draw(&mut canvas, &pose, inner, outer, 1.5);
+    assert_eq!(tris(&canvas) % 2, 0);
+
+    // fresh hit flashes towards white
+    let mut canvas = SimpleCanvas::new(1.0);
+    shape.draw(&mut canvas, &EnemyPose { hit_age: 0.0, ..pose }, inner, outer, 1.5);
+    let flashed = triangle_colour(&canvas, 5);
+    assert!(flashed.y > inner.y + 0.5 && flashed.w == 1.0);
+
+    // turns to face its velocity without overshooting, rolling goes with distance
+    let vel = EnemyShape { facing: Facing::Velocity, ..SHAPE_BLOB };
+    let mut a = 0.0;
+    for _ in 0..120 {
+        a = vel.update_angle(a, Vec2::new(0.0, -0.05), Vec2::new(1.0, 0.0), 1.0 / 60.0);
+        assert!(a <= 0.0 && a >= -PI / 2.0 - 1e-4);
+    }
+    assert!((a + PI / 2.0).abs() < 0.01);
+    // takes the short way round
+    let a = vel.update_angle(3.0, Vec2::new(-1.0, -0.01), Vec2::zero(), 1.0 / 60.0);
+    assert!(a > 3.0);
+    let roll = EnemyShape { facing: Facing::Roll(100.0), ..SHAPE_BLOB };
+    assert!((roll.update_angle(1.0, Vec2::new(0.03, 0.04), Vec2::zero(), 0.5) - 3.5).abs() < 1e-4);
+    // standing still keeps whatever angle it had
+    assert_eq!(vel.update_angle(1.0, Vec2::zero(), Vec2::zero(), 1.0), 1.0);
+}
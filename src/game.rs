@@ -6,7 +6,26 @@ use crate::kmath::*;
 use crate::kimg::*;
 use crate::level::*;
 use crate::enemy_repo::*;
-use crate::texture_buffer::TextureBuffer;
+use crate::enemy_shape::*;
+use crate::save::*;
+use crate::ui::*;
+use crate::sounds::*;
+use crate::audio::*;
+use crate::dsp::*;
+use crate::music::*;
+use crate::visibility::*;
+use crate::lighting::*;
+use crate::minimap::*;
+use crate::camera::*;
+use crate::particles::*;
+use crate::decals::*;
+use crate::level_texture::*;
+use crate::sprite_atlas::*;
+use crate::sprites::*;
+use crate::renderers::sprite_renderer::*;
+use crate::renderers::font_rendering::*;
+use crate::texture_buffer::*;
+use std::sync::Arc;
 use std::time::SystemTime;
 use std::time::Duration;
 
@@ -14,6 +33,37 @@ use std::time::Duration;
 const PLAYER_SPEED: f32 = 0.1;
 const PLAYER_RADIUS: f32 = 0.003;
 const PLAYER_INVUL_TIME: f32 = 0.3;
+const HIT_SOUND_INTERVAL: f32 = 0.15;
+const FOOTSTEP_INTERVAL: f32 = 0.35;
+const VISION_RAYS: usize = 360;
+const VISION_RANGE: f32 = 0.12;
+const VISION_SOFT_EDGE: f32 = 0.006;
+const CAMERA_ZOOM: f32 = 0.15;
+const HURT_TRAUMA: f32 = 0.5;
+const SCORCH_INTERVAL: f32 = 0.05;
+const LIGHT_MAP_H: usize = 90;
+const MAP_RES: usize = 256;
+const MINIMAP_RANGE: f32 = 0.3;
+const MAP_ZOOM_MIN: f32 = 0.1;
+const MAP_ZOOM_MAX: f32 = 1.5;
+const MAP_STAIRS_COLOUR: Vec4 = Vec4::new(0.9, 0.9, 0.3, 1.0);
+const MAP_PLAYER_COLOUR: Vec4 = Vec4::new(1.0, 1.0, 1.0, 1.0);
+
+// lamp fuel runs 0..1, the lamp never quite goes out but the dark gets close
+const FUEL_DRAIN: f32 = 1.0 / 240.0;
+const FUEL_LASER_DRAIN: f32 = 1.0 / 90.0;
+const FUEL_BIBLE_COST: f32 = 0.02;
+const FUEL_PICKUP_AMOUNT: f32 = 0.35;
+const FUEL_PICKUP_RADIUS: f32 = 0.008;
+const FUEL_PICKUPS_PER_FLOOR: usize = 4;
+const FUEL_DROP_CHANCE: f32 = 0.1;
+const FUEL_COLOUR: Vec4 = Vec4::new(0.9, 0.6, 0.1, 1.0);
+const LAMP_MIN: f32 = 0.35;
+const SHADE_SPAWN_INTERVAL: f32 = 3.0;
+const SHADE_SPAWN_DIST: f32 = 0.09;
+const MAX_SHADES: usize = 8;
+const LIGHT_AMBIENT: f32 = 0.2;
+const LIGHT_TINT: f32 = 0.25;
 const PLAYER_COLOUR_INNER: Vec4 = Vec4::grey(0.7);
 const PLAYER_COLOUR_OUTER: Vec4 = Vec4::grey(0.0);
 
@@ -26,10 +76,9 @@ const BIBLE_SPEED: f32 = 7.0;
 const BIBLE_SIZE: f32 = 0.006;
 const BIBLE_GROW_SPEED: f32 = 0.5;
 
-const FG_SAT: f32 = 0.8;
-const BG_SAT: f32 = 0.6;
-const FG_VAL: f32 = 0.15;
-const BG_VAL: f32 = 0.4;
+const PLAYER_CLASS: &str = "Paladin";
+const PLAYER_NAMES: [&str; 8] = ["Aldric", "Brother Anselm", "Cassia", "Dunstan", "Edda", "Father Osric", "Isolde", "Wystan"];
+
 
 pub struct Game {
     seed: u32,
@@ -37,14 +86,19 @@ pub struct Game {
     t: f32,
     stale: bool,
     l: Level,
-    camera: Rect,
-    zoom: f32,
+    camera: Camera,
 
     player_hp: f32,
+    player_xp: u32,
     player_damage_time: f32,
     player_pos: Vec2,
     player_bible_start: f32,
     player_bible_dir: bool,
+    player_item_on: [bool; 3],
+    player_fuel: f32,
+    player_heading: Vec2,
+    fuel_pickups: Vec<Vec2>,
+    next_shade_spawn: f32,
 
     enemy_pos: Vec<Vec2>,
     enemy_v: Vec<Vec2>,
@@ -54,19 +108,46 @@ pub struct Game {
     enemy_last_attack: Vec<f32>,
     enemy_seed: Vec<u32>,
     enemy_clip: Vec<i32>,
+    enemy_angle: Vec<f32>,
+    enemy_hit_time: Vec<f32>,
 
     enemies_pause: bool,
-    repo: EnemyRepo
+    repo: EnemyRepo,
+
+    level_texture: TextureHandle,
+    decals: DecalLayer,
+    next_scorch: f32,
+    light_texture: TextureHandle,
+    light_map: LightMap,
+    explored: ExploredMap,
+    map_texture: TextureHandle,
+    atlas: SpriteAtlas,
+    atlas_texture: TextureHandle,
+    map_open: bool,
+    map_view: Rect,
+
+    ui_state: UiState,
+
+    sounds: SoundQueue,
+    laser_sounding: bool,
+    last_hit_sound: f32,
+    next_footstep: f32,
+    music: Music,
+
+    particles: Particles,
+    laser_sparks: Emitter,
 }
 
 impl Game {
     fn advance_level(&mut self) {
         self.player_hp = 1.0;
         self.clear_enemies();
+        self.particles.clear();
         self.stale = true;
         self.l.floor += 1;
         self.l.seed += 1;
         self.l.gen();
+        self.explored = ExploredMap::new(&self.l, MAP_RES, MAP_RES);
         'OUTER:
         for i in 0..self.l.w {
             for j in 0..self.l.h {
@@ -76,8 +157,21 @@ impl Game {
                 }
             }
         }
+        self.camera.snap(self.player_pos);
+
 
 
+        // lamp oil lying about
+        self.fuel_pickups.clear();
+        let mut attempt = 0;
+        while self.fuel_pickups.len() < FUEL_PICKUPS_PER_FLOOR && attempt < 100 {
+            let si = khash(self.l.seed.wrapping_mul(712349111).wrapping_add(attempt));
+            let p = Vec2::new(krand(si), krand(khash(si)));
+            if self.l.point(p.x, p.y).walkable && self.l.wall_distance(p) > 0.005 {
+                self.fuel_pickups.push(p);
+            }
+            attempt += 1;
+        }
 
         let density = 0.2 + self.l.floor as f32 * 0.1;
         let st_len = if self.l.floor == 1 {
@@ -93,26 +187,26 @@ impl Game {
         let sh = 15;
         for i in 0..sw {
             for j in 0..sh {
-                let si = khash2i(i, j, self.l.seed * 124891247);
+                let si = khash2i(i, j, self.l.seed.wrapping_mul(124891247));
 
-                if chance(khash(self.seed * 1324147 + i as u32 * 124712547 + j as u32 * 131917), density) {
+                if chance(khash(self.seed.wrapping_mul(1324147).wrapping_add(i as u32 * 124712547).wrapping_add(j as u32 * 131917)), density) {
                     let x = i as f32 / sw as f32;
                     let y = j as f32 / sh as f32;
                     
                     let x = x + 1.0/sw as f32 * krand(khash2i(i, j, self.l.seed));
-                    let y = y + 1.0/sh as f32 * krand(khash2i(i, j, self.l.seed * 148971247));
+                    let y = y + 1.0/sh as f32 * krand(khash2i(i, j, self.l.seed.wrapping_mul(148971247)));
                     
                     let pp = self.l.point(x, y);
                     if pp.gtype == STAIRS_DOWN { continue; }
-                    let packdesc = self.repo.spawn_table[khash(si * 2312317) as usize % st_len].clone();
+                    let packdesc = self.repo.spawn_table[khash(si.wrapping_mul(2312317)) as usize % st_len].clone();
                     let pack_range = 0.04;
 
                     for (etype, qty) in packdesc {
                         for n in 0..qty {
-                            let si = si + etype as u32 * 2131241477 + n as u32 * 21312377;
+                            let si = si.wrapping_add((etype as u32).wrapping_mul(2131241477)).wrapping_add(n as u32 * 21312377);
                             // get the point and then optionally reject
                             let dx = pack_range * (krand(si) - 0.5);
-                            let dy = pack_range * (krand(si * 13123147) - 0.5);
+                            let dy = pack_range * (krand(si.wrapping_mul(13123147)) - 0.5);
                             let x = x + dx;
                             let y = y + dy;
 
@@ -137,17 +231,25 @@ impl Game {
 
 impl Default for Game {
     fn default() -> Self {
-        let l = Level::default();
-        let mut g = Game {
+        let mut g = Game::new(Level::default(), SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or(Duration::from_nanos(34123123)).subsec_nanos());
+        g.advance_level();
+        g
+    }
+}
+
+impl Game {
+    fn new(l: Level, seed: u32) -> Game {
+        let explored = ExploredMap::new(&l, MAP_RES, MAP_RES);
+        Game {
             frame: 0,
             t: 0.0,
             stale: true,
             l,
             player_pos: Vec2::new(0.0, 0.0),
             player_hp: 1.0,
+            player_xp: 0,
             player_damage_time: -100.0,
-            camera: Rect::new(0.0, 0.0, 1.0, 1.0),
-            zoom: 0.15,
+            camera: Camera::new(Vec2::new(0.5, 0.5), CAMERA_ZOOM, seed),
             enemy_pos: Vec::new(),
             enemy_kill: Vec::new(),
             enemy_hp: Vec::new(),
@@ -156,18 +258,111 @@ impl Default for Game {
             enemy_seed: Vec::new(),
             enemy_last_attack: Vec::new(),
             enemy_clip: Vec::new(),
+            enemy_angle: Vec::new(),
+            enemy_hit_time: Vec::new(),
             enemies_pause: false,
-            seed: SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or(Duration::from_nanos(34123123)).subsec_nanos(),
+            seed,
             repo: EnemyRepo::default(),
             player_bible_start: 0.0,
             player_bible_dir: false,
-        };
-        g.advance_level();
+            player_item_on: [false; 3],
+            player_fuel: 1.0,
+            player_heading: Vec2::new(0.0, -1.0),
+            fuel_pickups: Vec::new(),
+            next_shade_spawn: 0.0,
+            level_texture: TextureHandle::alloc(),
+            decals: DecalLayer::new(TextureBuffer::new(1, 1)),
+            next_scorch: 0.0,
+            light_texture: TextureHandle::alloc(),
+            light_map: LightMap::new(1, 1),
+            explored,
+            map_texture: TextureHandle::alloc(),
+            atlas: load_sprites(),
+            atlas_texture: TextureHandle::alloc(),
+            map_open: false,
+            map_view: Rect::unit(),
+            ui_state: UiState::default(),
+            sounds: SoundQueue::default(),
+            laser_sounding: false,
+            last_hit_sound: -100.0,
+            next_footstep: 0.0,
+            music: Music::new(seed),
+            particles: Particles::new(seed),
+            laser_sparks: Emitter::new(LASER_SPARKS),
+        }
+    }
+
+    pub fn to_save_data(&self) -> SaveData {
+        SaveData {
+            game_seed: self.seed,
+            level_seed: self.l.seed,
+            floor: self.l.floor,
+            t: self.t,
+            player_hp: self.player_hp,
+            player_xp: self.player_xp,
+            player_damage_time: self.player_damage_time,
+            player_pos: self.player_pos,
+            player_bible_start: self.player_bible_start,
+            player_bible_dir: self.player_bible_dir,
+            player_fuel: self.player_fuel,
+            fuel_pickups: self.fuel_pickups.clone(),
+            enemies: (0..self.enemy_pos.len()).map(|i| EnemySave {
+                etype: self.enemy_type[i] as u32,
+                hp_frac: self.enemy_hp[i] / self.repo.get(self.enemy_type[i]).initial_hp,
+                pos: self.enemy_pos[i],
+                v: self.enemy_v[i],
+                last_attack: self.enemy_last_attack[i],
+                seed: self.enemy_seed[i],
+                clip: self.enemy_clip[i],
+            }).collect(),
+        }
+    }
+
+    pub fn from_save_data(sd: &SaveData) -> Game {
+        let mut g = Game::new(Level::new(sd.level_seed, sd.floor), sd.game_seed);
+        g.t = sd.t;
+        g.player_hp = sd.player_hp;
+        g.player_xp = sd.player_xp;
+        g.player_damage_time = sd.player_damage_time;
+        g.player_pos = sd.player_pos;
+        g.camera.snap(sd.player_pos);
+        g.player_bible_start = sd.player_bible_start;
+        g.player_bible_dir = sd.player_bible_dir;
+        g.player_fuel = sd.player_fuel;
+        g.fuel_pickups = sd.fuel_pickups.clone();
+        for e in sd.enemies.iter() {
+            let etype = e.etype as usize;
+            if etype >= g.repo.enemies.len() {
+                println!("dropping saved enemy with unknown type {}", etype);
+                continue;
+            }
+            let hp = e.hp_frac * g.repo.get(etype).initial_hp;
+            g.spawn_enemy(etype, hp, e.pos, e.v, e.seed);
+            let i = g.enemy_pos.len() - 1;
+            g.enemy_last_attack[i] = e.last_attack;
+            g.enemy_clip[i] = e.clip;
+        }
         g
     }
 }
 
 impl Demo for Game {
+    fn on_close(&mut self, outputs: &mut FrameOutputs) {
+        outputs.free_texture.extend_from_slice(&[self.level_texture, self.light_texture, self.map_texture, self.atlas_texture]);
+    }
+
+    fn on_exit(&mut self) {
+        // dead runs dont get to be continued
+        if self.player_hp > 0.0 {
+            match self.to_save_data().write_to_file(SAVE_PATH) {
+                Ok(()) => println!("saved run to {}", SAVE_PATH),
+                Err(e) => println!("couldnt save run: {}", e),
+            }
+        } else {
+            delete_save(SAVE_PATH);
+        }
+    }
+
     fn frame(&mut self, inputs: &FrameInputs, outputs: &mut FrameOutputs) {
         self.frame += 1;
 
@@ -182,39 +377,33 @@ impl Demo for Game {
             self.enemies_pause = !self.enemies_pause;
         }
 
+        if inputs.key_pressed(VirtualKeyCode::M) {
+            self.map_open = !self.map_open;
+            self.map_view = Rect::new_centered(self.player_pos.x, self.player_pos.y, 0.5 * inputs.screen_rect.aspect(), 0.5);
+        }
+
         if inputs.key_pressed(VirtualKeyCode::R) {
             self.player_hp = 1.0;
+            self.player_xp = 0;
+            self.player_fuel = 1.0;
             self.l.floor = 0;
             self.advance_level();
         }
         if inputs.key_pressed(VirtualKeyCode::Return) {
             if self.player_pos.dist(self.l.stairs_down) < 0.1 {
+                self.sounds.play(STAIRS_DESCEND);
                 self.advance_level();
             }
         }
 
-        outputs.glyphs.push_str(&format!("Level {}", self.l.floor), 0.0, 0.0, 0.01, 0.01, 3.0, Vec4::new(1.0, 1.0, 1.0, 1.0));
-
+        // a new floor reuses the same handles, set_texture replaces whatever was there
         if self.stale {
-            let hue = krand(inputs.seed) * 360.0;
-            let fg = Vec4::new(hue, FG_SAT, FG_VAL, 1.0).hsv_to_rgb();
-            let bg = Vec4::new(hue, BG_SAT, BG_VAL, 1.0).hsv_to_rgb();
-            
-            let w = 2000;
-            let h = 2000;
-            let mut tb = TextureBuffer::new(w, h);
-            for i in 0..w {
-                for j in 0..h {
-                    let pp = self.l.point(i as f32 / w as f32, j as f32 / h as f32);
-                    let c = if pp.walkable {
-                        fg
-                    } else {
-                        bg
-                    };
-                    tb.set(i as i32, (h - j - 1) as i32, c);
-                }
-            }
-            outputs.set_texture.push((tb, 0));
+            let tb = level_texture(&self.l, palette_for_floor(self.l.floor), 2000, 2000);
+            outputs.set_texture.push((Arc::new(tb.clone()), self.level_texture, TextureOptions::default()));
+            self.decals = DecalLayer::new(tb);
+            outputs.set_texture.push((Arc::new(self.explored.texture()), self.map_texture, TextureOptions::default()));
+            outputs.set_texture.push((Arc::new(self.atlas.tb.clone()), self.atlas_texture, TextureOptions { filter: TextureFilter::Linear, wrap: TextureWrap::Clamp }));
+            outputs.bus = Some(BusConfig::for_floor(self.l.floor));
             self.stale = false;
         }
 
@@ -230,6 +419,9 @@ impl Demo for Game {
             pv.x = 1.0;
         };
         pv = pv.normalize();
+        if pv.magnitude() > 0.0 {
+            self.player_heading = pv;
+        }
 
         let pspeed = dt * PLAYER_SPEED;
         let new_pos = self.player_pos + pspeed * pv;
@@ -237,6 +429,13 @@ impl Demo for Game {
         if let Some(pen) = self.l.collide_circle(self.player_pos, PLAYER_RADIUS) {
             self.player_pos = self.player_pos - pen;
         }
+        if pspeed > 0.0 && pv.magnitude() > 0.0 && self.t >= self.next_footstep {
+            self.sounds.play(FOOTSTEP);
+            self.particles.burst(&DUST, self.player_pos, -1.0 * pv);
+            self.next_footstep = self.t + FOOTSTEP_INTERVAL;
+        }
+
+        self.update_lamp(dt);
 
         if !self.enemies_pause {
             // update enemies velocity
@@ -246,7 +445,7 @@ impl Demo for Game {
                 if er.is_projectile {
                     continue;
                 }
-                let wander_vec = Vec2::new(0.5 - noise1d(self.t, self.enemy_seed[i]), 0.5 - noise1d(self.t, self.enemy_seed[i] * 12390471));
+                let wander_vec = Vec2::new(0.5 - noise1d(self.t, self.enemy_seed[i]), 0.5 - noise1d(self.t, self.enemy_seed[i].wrapping_mul(12390471)));
                 let mut will = Vec2::zero();
                 if self.enemy_pos[i].dist(self.player_pos) < er.acquisition_radius {
                     will = (self.player_pos - self.enemy_pos[i]).normalize();
@@ -263,11 +462,14 @@ impl Demo for Game {
                 let move_vec = self.enemy_v[i] * dt;
                 
                 self.enemy_pos[i] = self.enemy_pos[i] + move_vec;
+                self.enemy_angle[i] = er.shape.update_angle(self.enemy_angle[i], self.enemy_v[i], self.player_pos - self.enemy_pos[i], dt);
 
                 // collide with terrain
                 if let Some(pen) = self.l.collide_circle(self.enemy_pos[i], er.radius) {
                     if er.is_projectile {
                         self.enemy_kill[i] = true;
+                        let sparks = EmitterDesc { colour_start: er.colour_inner, colour_end: Vec4 { w: 0.0, ..er.colour_inner }, ..WALL_SPARKS };
+                        self.particles.burst(&sparks, self.enemy_pos[i], self.l.wall_dir(self.enemy_pos[i]));
                     } else {
                         self.enemy_pos[i] = self.enemy_pos[i] - pen;
                     }
@@ -291,6 +493,9 @@ impl Demo for Game {
                 if self.t - self.player_damage_time > PLAYER_INVUL_TIME {
                     self.player_hp -= er.melee_damage;
                     self.player_damage_time = self.t;
+                    self.sounds.play(PLAYER_HURT);
+                    self.camera.add_trauma(HURT_TRAUMA);
+                    self.decals.stamp(&DecalDesc { radius: BLOOD_SPLAT.radius * 0.6, ..BLOOD_SPLAT }, self.player_pos, khash(self.frame as u32));
                 }
             }
             
@@ -322,6 +527,7 @@ impl Demo for Game {
         }
 
         // shoot projectiles
+        let mut fired: Option<Vec2> = None;
         for i in 0..self.enemy_type.len() {
             let etype = self.enemy_type[i];
             let er = self.repo.get(etype);
@@ -353,37 +559,82 @@ impl Demo for Game {
                 let si = self.enemy_seed[i];
                 self.spawn_enemy(spawn_etype, ser.initial_hp, self.enemy_pos[i], dir * ser.speed_to_target, si);
                 self.enemy_last_attack[i] = self.t;
+                // the one nearest the player is the one you hear
+                if fired.map(|p| p.dist(self.player_pos) > self.enemy_pos[i].dist(self.player_pos)).unwrap_or(true) {
+                    fired = Some(self.enemy_pos[i]);
+                }
                 if er.clip_size != -1 {
                     self.enemy_clip[i] -= 1;
                 }
             }
         }
         
-        self.camera = Rect::new_centered(self.player_pos.x, self.player_pos.y, self.zoom * inputs.screen_rect.aspect(), self.zoom);
-        let mouse_world = inputs.mouse_pos.transform(inputs.screen_rect, self.camera);
-        let cam_center = self.player_pos.lerp(mouse_world, 0.2);
-        self.camera = Rect::new_centered(cam_center.x, cam_center.y, self.zoom * inputs.screen_rect.aspect(), self.zoom);
+        // one per frame or a room full of shooters is deafening
+        if let Some(pos) = fired {
+            self.play_at(PROJECTILE_FIRE, pos);
+        }
+
+        if !self.map_open {
+            self.camera.zoom_by(inputs.scroll_delta);
+        }
+        let mouse_world = self.camera.screen_to_world(inputs.mouse_pos);
+        self.camera.update(inputs.dt, self.player_pos, mouse_world, inputs.screen_rect.aspect());
+        outputs.listener = Some(Listener { pos: self.player_pos, half_width: self.camera.view().w / 2.0 });
         
-        let r = self.camera.pseudo_inverse();
-        let p_screen_pos = self.player_pos.transform(Rect::unit(), r);
+        let cam = self.camera;
+        let p_screen_pos = cam.world_to_screen(self.player_pos);
         
-        if inputs.lmb == KeyStatus::Pressed && self.player_hp > 0.0 {
-            let rising = inputs.lmb == KeyStatus::JustPressed;
-            let falling = inputs.lmb == KeyStatus::JustReleased;
-            self.do_item(inputs, outputs, 0, dt, rising, falling);
+        // items are used while their button is held, unless the press started on the ui
+        self.laser_sparks.active = false;
+        let (lmb_free, rmb_free) = self.ui_state.game_mouse(inputs);
+        let held = [
+            (0, lmb_free && inputs.lmb == KeyStatus::Pressed),
+            (1, rmb_free && (inputs.rmb == KeyStatus::Pressed || inputs.rmb == KeyStatus::JustPressed)),
+        ];
+        for (id, held) in held {
+            let was_on = self.player_item_on[id];
+            let on = held && self.player_hp > 0.0 && !self.map_open;
+            self.player_item_on[id] = on;
+            if on {
+                self.do_item(inputs, outputs, id as u32, dt, !was_on, false);
+            }
         }
 
-        if inputs.rmb == KeyStatus::Pressed || inputs.rmb == KeyStatus::JustPressed && self.player_hp > 0.0 {
-            let rising = inputs.rmb == KeyStatus::JustPressed;
-            let falling = inputs.rmb == KeyStatus::JustReleased;
-            self.do_item(inputs, outputs, 1, dt, rising, falling);
+        // threat is how many things are coming for you
+        let hunting = (0..self.enemy_pos.len()).filter(|&i| {
+            let er = self.repo.get(self.enemy_type[i]);
+            !er.is_projectile && self.enemy_pos[i].dist(self.player_pos) < er.acquisition_radius
+        }).count();
+        self.music.update(dt, self.l.floor, hunting as f32 / THREAT_ENEMIES, &mut self.sounds);
+
+        // laser hum holds for as long as the beam is out
+        let laser_on = self.player_item_on[0];
+        if laser_on && !self.laser_sounding {
+            self.sounds.start(LASER_HUM, SOUND_ID_LASER);
+        } else if !laser_on && self.laser_sounding {
+            self.sounds.stop(LASER_HUM, SOUND_ID_LASER);
         }
+        self.laser_sounding = laser_on;
+
         
 
 
-        outputs.draw_texture.push((r, 0, 1.0));
+        outputs.draw_texture.push((cam.world_rect_to_screen(Rect::unit()), Rect::unit(), self.level_texture, 1.0));
+        self.lighting(inputs, outputs);
 
-        let p_radius = PLAYER_RADIUS * r.h;
+        // altar and torches
+        let white = Vec4::new(1.0, 1.0, 1.0, 1.0);
+        self.put_sprite(outputs, ALTAR, self.l.altar, 0.012, 0.0, white, 1.3);
+        for p in self.fuel_pickups.iter() {
+            self.put_sprite(outputs, FUEL, *p, 0.005, 0.0, white, 1.4);
+        }
+        for (i, torch) in self.l.torches.iter().enumerate() {
+            let flame = TORCH_LIGHT.at(*torch, self.torch_seed(i)).intensity_at_time(self.t) / TORCH_LIGHT.intensity;
+            let tint = Vec4::new(1.0, 1.0, 1.0, 0.7 + 0.3 * flame.min(1.0));
+            self.put_sprite(outputs, TORCH, *torch, 0.006 * (0.8 + 0.4 * flame), 0.0, tint, 1.4);
+        }
+
+        let p_radius = cam.world_len_to_screen(PLAYER_RADIUS);
         outputs.canvas.put_circle(p_screen_pos, p_radius * 1.2, 1.5, PLAYER_COLOUR_OUTER);
         let player_colour = if self.t - self.player_damage_time < PLAYER_INVUL_TIME {
             Vec4::new(1.0, 1.0, 1.0, 1.0)
@@ -393,29 +644,35 @@ impl Demo for Game {
         outputs.canvas.put_circle(p_screen_pos, p_radius * 1.0, 1.6, player_colour);
 
         for i in 0..self.enemy_pos.len() {
-            let ep_screen = self.enemy_pos[i].transform(Rect::unit(), r);
+            let ep_screen = cam.world_to_screen(self.enemy_pos[i]);
             let etype = self.enemy_type[i];
             let er = self.repo.get(etype);
-            let e_radius = er.radius * r.h;
-            outputs.canvas.put_circle(ep_screen, e_radius * 1.2, 1.5, er.colour_outer);
-            outputs.canvas.put_circle(ep_screen, e_radius * 1.0, 1.6, er.colour_inner);
-
+            let pose = EnemyPose {
+                pos: ep_screen,
+                radius: cam.world_len_to_screen(er.radius),
+                angle: self.enemy_angle[i],
+                look: (self.player_pos - self.enemy_pos[i]).normalize(),
+                hit_age: self.t - self.enemy_hit_time[i],
+            };
+            er.shape.draw(&mut outputs.canvas, &pose, er.colour_inner, er.colour_outer, 1.5);
         }
 
-        let player_health_rect = Rect::new(0.1, 0.8, 0.1, 0.1).dilate_pc(-0.2);
-        outputs.canvas.put_rect(player_health_rect.dilate_pc(0.1), 3.0, Vec4::grey(0.0));
-        outputs.canvas.put_rect(player_health_rect.child(0.0, 1.0 - self.player_hp.max(0.0), 1.0, self.player_hp.max(0.0)), 3.1, Vec4::new(1.0, 0.0, 0.0, 1.0));
+        self.particles.emit(&mut self.laser_sparks, dt);
+        self.particles.update(dt, &|p| self.l.wall_distance(p), &|p| self.l.wall_dir(p));
+        self.particles.draw(&mut outputs.canvas, &|p| cam.world_to_screen(p), cam.world_len_to_screen(1.0), 1.7);
+
+        self.hud(inputs, outputs);
 
         // stairs up
-        let s = r.h * 0.02;
-        let stairs_up_rect = self.l.stairs_up.transform(Rect::unit(), r).rect_centered(s, s);
+        let s = cam.world_len_to_screen(0.02);
+        let stairs_up_rect = cam.world_to_screen(self.l.stairs_up).rect_centered(s, s);
         let stair_colour = Vec4::grey(0.4);
         outputs.canvas.put_rect(stairs_up_rect.child(0.0, 0.0, 0.33, 1.0), 1.3, stair_colour);
         outputs.canvas.put_rect(stairs_up_rect.child(0.33, 0.33, 0.33, 1.0 - 0.33), 1.3, stair_colour);
         outputs.canvas.put_rect(stairs_up_rect.child(0.66, 0.66, 1.0 - 0.66, 1.0 - 0.66), 1.3, stair_colour);
         
         // stairs down
-        let stairs_down_rect = self.l.stairs_down.transform(Rect::unit(), r).rect_centered(s, s).child(0.0, 0.0, 1.0, 0.8);
+        let stairs_down_rect = cam.world_to_screen(self.l.stairs_down).rect_centered(s, s).child(0.0, 0.0, 1.0, 0.8);
         outputs.canvas.put_rect(stairs_down_rect, 1.1, stair_colour);
         let stairs_down_rect = stairs_down_rect.dilate_pc(-0.1);
         outputs.canvas.put_rect(stairs_down_rect, 1.2, Vec4::new(0.0, 0.0, 0.0, 1.0));
@@ -426,76 +683,64 @@ impl Demo for Game {
         outputs.canvas.put_rect(stairs_down_rect.child(0.33, 0.33, 0.33, 1.0 - 0.33), 1.3, stair_colour);
         outputs.canvas.put_rect(stairs_down_rect.child(0.66, 0.66, 1.0 - 0.66, 1.0 - 0.66), 1.3, stair_colour);
 
-        // vignette
-        let vw = 400;
-        let vh = 400;
+        // vignette: dark outside what the player can see, dimming with distance inside it
+        // goes on the overlay so it shades the level texture, light map and sprites drawn after the canvas
+        let outerd = noise1d(self.t + 0.41, self.seed.wrapping_mul(141971237)) - 0.5;
+        let sight = VisibilityPolygon::new(&|p| self.l.wall_distance(p), self.player_pos, VISION_RAYS, (VISION_RANGE + outerd * 0.01) * self.lamp());
+        let to_screen = |p: Vec2| cam.world_to_screen(p);
+        sight.draw_darkness(&mut outputs.overlay, &to_screen, &|d| (5.0 * d).min(1.0), VISION_SOFT_EDGE, cam.view().w + cam.view().h, 2.5);
+        self.explored.reveal(&sight);
+        if let Some((patch, x, y)) = self.explored.take_dirty_patch() {
+            outputs.update_texture.push((patch, self.map_texture, x, y));
+        }
+        if self.map_open {
+            self.full_map(inputs, outputs);
+        } else {
+            self.minimap(inputs, outputs);
+        }
 
 
-        let mut mask_vals = vec![0.0; vw * vh];
-        // now set all to black if it fails raycast
-        // then erode / soften
+        // let d_mouse_world = self.l.wall_distance(mouse_world);
+        // outputs.canvas.put_circle(inputs.mouse_pos, cam.world_len_to_screen(d_mouse_world), 1.0, Vec4::new(0.7, 0.4, 0.0, 1.0));
 
-        for i in 0..vw {
-            for j in 0..vh {
-                let x = (0.5 + i as f32) / vw as f32;
-                let y = (0.5 + j as f32) / vh as f32;
-                let p = Vec2::new(x as f32, y);
-                let world_pos = p.transform(Rect::unit(), self.camera);
-                
-                let d = (world_pos-self.player_pos).magnitude();
 
-                let outerd = noise1d(self.t + 0.41, self.seed * 141971237) - 0.5;
-                if (self.l.ray_intersects_wall(self.player_pos, world_pos).is_some() && d > 0.01) || d > 0.12 + outerd * 0.01 {
-                    mask_vals[j*vw + i] = 1.0;
-                } else {
-                    mask_vals[j*vw + i] = (5.0*d).min(1.0);
-                }
-            }
+        self.cull_enemies();
+        if let Some((patch, x, y)) = self.decals.take_dirty_patch() {
+            outputs.update_texture.push((patch, self.level_texture, x, y));
         }
+        outputs.sounds.append(&mut self.sounds.commands);
+    }
+}
 
-        for _erosion in 0..6 {
-            let mut mask_vals_new = mask_vals.clone();
-            for i in 0..vw {
-                for j in 0..vh {
-                    // if any 4neighbour has any alpha we divide by 2
-                    let dx = [-1, 0, 1, 0];
-                    let dy = [0, -1, 0, 1];
+impl Game {
+    fn torch_seed(&self, i: usize) -> u32 {
+        khash(self.l.seed.wrapping_mul(91238417).wrapping_add(i as u32))
+    }
 
-                    
-                    for n in 0..4 {
-                        let nx = i as i32 + dx[n];
-                        let ny = j as i32 + dy[n];
-                        if nx < 0 { continue; }
-                        if ny < 0 { continue; }
-                        if nx > vw as i32 - 1 { continue; }
-                        if ny > vh as i32 - 1 { continue; }
-                        if mask_vals[vw * ny as usize + nx as usize] < 1.0 {
-                            mask_vals_new[vw * j + i] *= 0.9;
-                            break;
-                        }
-                    }
-                }
-            }
-            mask_vals = mask_vals_new;
+    // player, altar, torches and projectiles all give off light, shadows come from the level sdf
+    fn lighting(&mut self, inputs: &FrameInputs, outputs: &mut FrameOutputs) {
+        let lamp = self.lamp();
+        let mut lights = vec![Light { radius: PLAYER_LIGHT.radius * lamp, intensity: PLAYER_LIGHT.intensity * lamp, ..PLAYER_LIGHT.at(self.player_pos, self.seed) }];
+        lights.push(ALTAR_LIGHT.at(self.l.altar, self.l.seed));
+        for (i, torch) in self.l.torches.iter().enumerate() {
+            lights.push(TORCH_LIGHT.at(*torch, self.torch_seed(i)));
         }
-
-
-        let mut tb = TextureBuffer::new(vw, vh);
-
-        for i in 0..vw {
-            for j in 0..vh {
-                tb.set(i as i32, vh as i32 - j as i32 - 1, Vec4::new(0.0, 0.0, 0.0, mask_vals[j*vw + i]));
+        for i in 0..self.enemy_pos.len() {
+            let er = self.repo.get(self.enemy_type[i]);
+            if er.is_projectile {
+                lights.push(Light { colour: er.colour_inner, ..PROJECTILE_LIGHT.at(self.enemy_pos[i], self.enemy_seed[i]) });
             }
         }
-        outputs.set_texture.push((tb, 1));
-        outputs.draw_texture.push((inputs.screen_rect, 1, 2.5));
-
-
-        // let d_mouse_world = self.l.wall_distance(mouse_world);
-        // outputs.canvas.put_circle(inputs.mouse_pos, d_mouse_world * r.h, 1.0, Vec4::new(0.7, 0.4, 0.0, 1.0));
 
+        let w = (LIGHT_MAP_H as f32 * inputs.screen_rect.aspect()).round() as usize;
+        if self.light_map.w != w || self.light_map.h != LIGHT_MAP_H {
+            self.light_map = LightMap::new(w, LIGHT_MAP_H);
+        }
+        let ambient = Vec4::new(LIGHT_AMBIENT, LIGHT_AMBIENT, LIGHT_AMBIENT, 1.0);
+        self.light_map.compute(&|p| self.l.wall_distance(p), self.camera.view(), &lights, ambient, self.t);
 
-        self.cull_enemies();
+        outputs.set_texture.push((Arc::new(self.light_map.texture(LIGHT_TINT)), self.light_texture, TextureOptions { filter: TextureFilter::Linear, wrap: TextureWrap::Clamp }));
+        outputs.draw_texture.push((inputs.screen_rect, Rect::unit(), self.light_texture, 1.05));
     }
 }
 
@@ -512,6 +757,8 @@ impl Game {
         self.enemy_type = Vec::new();
         self.enemy_seed = Vec::new();
         self.enemy_clip = Vec::new();
+        self.enemy_angle = Vec::new();
+        self.enemy_hit_time = Vec::new();
     }
 
     pub fn cull_enemies(&mut self) {
@@ -522,6 +769,20 @@ impl Game {
             }
             i -= 1;
             if self.enemy_kill[i] || self.enemy_hp[i] < 0.0 {
+                let er = self.repo.get(self.enemy_type[i]);
+                if self.enemy_hp[i] < 0.0 && !er.is_projectile {
+                    self.player_xp += (er.initial_hp * 10.0).ceil() as u32;
+                    let pos = self.enemy_pos[i];
+                    self.play_at(ENEMY_DEATH, pos);
+                    let burst = EmitterDesc { colour_start: er.colour_inner, colour_end: Vec4 { w: 0.0, ..er.colour_outer }, ..DEATH_BURST };
+                    self.particles.burst(&burst, pos, Vec2::new(1.0, 0.0));
+                    let seed = self.enemy_seed[i];
+                    self.decals.stamp(&BLOOD_SPLAT, pos, seed);
+                    self.decals.stamp(&DecalDesc { colour: er.colour_inner * 0.4, radius: er.radius, ..CORPSE }, pos, khash(seed));
+                    if chance(khash(self.enemy_seed[i].wrapping_mul(31234117)), FUEL_DROP_CHANCE) {
+                        self.fuel_pickups.push(pos);
+                    }
+                }
                 self.enemy_kill.swap_remove(i);
                 self.enemy_pos.swap_remove(i);
                 self.enemy_hp.swap_remove(i);
@@ -530,6 +791,8 @@ impl Game {
                 self.enemy_last_attack.swap_remove(i);
                 self.enemy_seed.swap_remove(i);
                 self.enemy_clip.swap_remove(i);
+                self.enemy_angle.swap_remove(i);
+                self.enemy_hit_time.swap_remove(i);
             }
         }
     }
@@ -543,14 +806,16 @@ impl Game {
         self.enemy_pos.push(pos);
         self.enemy_seed.push(seed);
         self.enemy_clip.push(0);
+        self.enemy_angle.push(v.y.atan2(v.x));
+        self.enemy_hit_time.push(-100.0);
     }
 }
 
 impl Game {
     pub fn do_item(&mut self, inputs: &FrameInputs, outputs: &mut FrameOutputs, id: u32, dt: f32, rising: bool, falling: bool) {
-        let mouse_world = inputs.mouse_pos.transform(inputs.screen_rect, self.camera);
-        let r = self.camera.pseudo_inverse();
-        let p_screen_pos = self.player_pos.transform(Rect::unit(), r);
+        let cam = self.camera;
+        let mouse_world = cam.screen_to_world(inputs.mouse_pos);
+        let p_screen_pos = cam.world_to_screen(self.player_pos);
 
         if id == 0 {    // laser
             let laser_dir = (mouse_world - self.player_pos).normalize();
@@ -585,14 +850,23 @@ impl Game {
             }
             if let Some(laser_enemy_id) = nearest_enemy_id {
                 laser_t = nearest_enemy_t;
-                self.enemy_hp[laser_enemy_id] -= dt * LASER_DPS;
+                self.hurt_enemy(laser_enemy_id, dt * LASER_DPS);
+            } else if self.t >= self.next_scorch {
+                self.next_scorch = self.t + SCORCH_INTERVAL;
+                self.decals.stamp(&SCORCH, self.player_pos + laser_t * laser_dir, khash(self.frame as u32));
             }
+            self.player_fuel = (self.player_fuel - dt * FUEL_LASER_DRAIN).max(0.0);
+            self.laser_sparks.active = true;
+            self.laser_sparks.pos = self.player_pos + laser_t * laser_dir;
+            self.laser_sparks.dir = -1.0 * laser_dir;
 
-            outputs.canvas.put_line(p_screen_pos, p_screen_pos + r.h * laser_t * laser_dir, LASER_W * r.h, 1.4, Vec4::new(1.0, 0.0, 0.0, 1.0));
+            outputs.canvas.put_line(p_screen_pos, p_screen_pos + cam.world_len_to_screen(laser_t) * laser_dir, cam.world_len_to_screen(LASER_W), 1.4, Vec4::new(1.0, 0.0, 0.0, 1.0));
         } else if id == 1 { // bible
             if rising {
                 self.player_bible_start = self.t;
                 self.player_bible_dir = !self.player_bible_dir;
+                self.sounds.play(BIBLE_WHOOSH);
+                self.player_fuel = (self.player_fuel - FUEL_BIBLE_COST).max(0.0);
             }
             let t_bible = self.t - self.player_bible_start;
             let radius_multiplier = (t_bible*BIBLE_GROW_SPEED).min(1.0);
@@ -601,22 +875,427 @@ impl Game {
             if self.player_bible_dir {
                 bible_phase *= -1.0;
             }
+            let bp1 = self.player_pos.offset_r_theta(bible_radius, bible_phase);
+            let bp2 = self.player_pos.offset_r_theta(bible_radius, bible_phase - PI);
             for i in 0..self.enemy_pos.len() {
-                let bp1 = self.player_pos.offset_r_theta(bible_radius, bible_phase);
-                let bp2 = self.player_pos.offset_r_theta(bible_radius, bible_phase - PI);
                 let mindist = self.enemy_pos[i].dist(bp1).min(self.enemy_pos[i].dist(bp2));
                 let er = self.repo.get(self.enemy_type[i]);
                 let hit = mindist < BIBLE_SIZE + er.radius;
                 if hit {
-                    self.enemy_hp[i] -= dt * BIBLE_DPS;
+                    self.hurt_enemy(i, dt * BIBLE_DPS);
                 }
-                let bp1 = bp1.transform(Rect::unit(), r);
-                let bp2 = bp2.transform(Rect::unit(), r);
-                outputs.canvas.put_rect(bp1.rect_centered(BIBLE_SIZE * 1.5 * r.h, BIBLE_SIZE * 1.5 * r.h), 1.5, Vec4::new(0.0, 0.0, 1.0, 1.0));
-                outputs.canvas.put_rect(bp2.rect_centered(BIBLE_SIZE * 1.5 * r.h, BIBLE_SIZE * 1.5 * r.h), 1.5, Vec4::new(0.0, 0.0, 1.0, 1.0));
             }
+            let white = Vec4::new(1.0, 1.0, 1.0, 1.0);
+            self.put_sprite(outputs, BIBLE, bp1, BIBLE_SIZE * 1.5, bible_phase, white, 1.5);
+            self.put_sprite(outputs, BIBLE, bp2, BIBLE_SIZE * 1.5, bible_phase - PI, white, 1.5);
         } else if id == 2 { // giant sword
 
         }
     }
-}
\ No newline at end of file
+}
+
+impl Game {
+    // everything out of the atlas goes into one batch, pos and size are world space
+    fn put_sprite(&self, outputs: &mut FrameOutputs, name: &str, pos: Vec2, size: f32, rotation: f32, tint: Vec4, depth: f32) {
+        if outputs.sprites.last().map(|b| b.atlas) != Some(self.atlas_texture) {
+            outputs.sprites.push(SpriteBatch::new(self.atlas_texture, self.camera.aspect));
+        }
+        let s = self.camera.world_len_to_screen(size);
+        let sprite = Sprite {
+            pos: self.camera.world_to_screen(pos),
+            size: Vec2::new(s, s),
+            rotation,
+            uv: self.atlas.uv(name),
+            tint,
+            depth,
+        };
+        outputs.sprites.last_mut().unwrap().push(&sprite);
+    }
+
+    // how far the item still is from full strength for the hud, 0 is ready
+    // the laser is full strength the moment its out, the bible has to grow out to its orbit
+    fn item_warmup(&self, id: usize) -> f32 {
+        if id == 1 && self.player_item_on[1] {
+            (1.0 - (self.t - self.player_bible_start) * BIBLE_GROW_SPEED).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+
+    fn hurt_enemy(&mut self, i: usize, amount: f32) {
+        self.enemy_hp[i] -= amount;
+        if self.t - self.enemy_hit_time[i] > HIT_PULSE {
+            self.enemy_hit_time[i] = self.t;
+        }
+        self.hit_sound(self.enemy_pos[i]);
+    }
+
+    // damage is continuous so hits are rate limited rather than per frame
+    fn hit_sound(&mut self, pos: Vec2) {
+        if self.t - self.last_hit_sound > HIT_SOUND_INTERVAL {
+            self.last_hit_sound = self.t;
+            self.play_at(ENEMY_HIT, pos);
+        }
+    }
+
+    // muffled if theres rock in the way, eg its in the next cell over
+    fn play_at(&mut self, sd: SoundDesc, pos: Vec2) {
+        let occluded = self.l.ray_intersects_wall(self.player_pos, pos).is_some();
+        self.sounds.play_at(sd, pos, occluded);
+    }
+
+    // how much of the lamps full range and brightness is left
+    fn lamp(&self) -> f32 {
+        lerp(LAMP_MIN, 1.0, self.player_fuel)
+    }
+
+    fn update_lamp(&mut self, dt: f32) {
+        self.player_fuel = (self.player_fuel - dt * FUEL_DRAIN).max(0.0);
+
+        let before = self.fuel_pickups.len();
+        let player_pos = self.player_pos;
+        self.fuel_pickups.retain(|p| p.dist(player_pos) > FUEL_PICKUP_RADIUS);
+        let picked = before - self.fuel_pickups.len();
+        if picked > 0 {
+            self.player_fuel = (self.player_fuel + picked as f32 * FUEL_PICKUP_AMOUNT).min(1.0);
+            self.sounds.play(FUEL_PICKUP);
+        }
+
+        // things that live in the dark come for you once the lamp is dry
+        if self.player_fuel > 0.0 || dt == 0.0 {
+            self.next_shade_spawn = self.t + SHADE_SPAWN_INTERVAL;
+            return;
+        }
+        if self.t < self.next_shade_spawn {
+            return;
+        }
+        self.next_shade_spawn = self.t + SHADE_SPAWN_INTERVAL;
+        let shades = self.enemy_type.iter().filter(|t| **t == self.repo.shade).count();
+        if shades >= MAX_SHADES {
+            return;
+        }
+        let si = khash(self.seed.wrapping_mul(98123477).wrapping_add(self.frame as u32));
+        for attempt in 0..8 {
+            let theta = krand(khash(si.wrapping_add(attempt))) * 2.0 * PI;
+            let p = self.player_pos.offset_r_theta(SHADE_SPAWN_DIST, theta);
+            if self.l.point(p.x, p.y).walkable && self.l.wall_distance(p) > 0.004 {
+                let er = self.repo.get(self.repo.shade);
+                self.spawn_enemy(self.repo.shade, er.initial_hp, p, Vec2::zero(), si);
+                return;
+            }
+        }
+    }
+
+    // stairs and the player arrow over a map texture drawn in screen rect r showing world rect view
+    fn map_markers(&self, outputs: &mut FrameOutputs, r: Rect, view: Rect, depth: f32) {
+        let s = r.h * 0.02 * (MINIMAP_RANGE / view.h).clamp(0.5, 2.0);
+        for stairs in [self.l.stairs_up, self.l.stairs_down] {
+            let p = stairs.transform(view, r);
+            if self.explored.is_explored(stairs) && r.contains(p) {
+                outputs.canvas.put_rect(p.rect_centered(s, s), depth, MAP_STAIRS_COLOUR);
+            }
+        }
+        let p = self.player_pos.transform(view, r);
+        if r.contains(p) {
+            let h = self.player_heading;
+            let side = Vec2::new(-h.y, h.x);
+            outputs.canvas.put_triangle(p + s * h, p - 0.6 * s * h + 0.5 * s * side, p - 0.6 * s * h - 0.5 * s * side, depth, MAP_PLAYER_COLOUR);
+        }
+    }
+
+    fn minimap(&mut self, inputs: &FrameInputs, outputs: &mut FrameOutputs) {
+        let mut ui = Ui::new(inputs, outputs, &mut self.ui_state, 3.0);
+        let ch = ui.theme.char_h;
+        let pad = ch * 0.5;
+        let size = ch * 12.0;
+        let panel = Rect::new(inputs.screen_rect.right() - size - pad, pad, size, size);
+        ui.panel(panel);
+        ui.label(Rect::new(panel.x, panel.bot() + ch * 0.2, panel.w, ch), "M: map", TextAlign::Right, ui.theme.text_dim);
+        ui.finish();
+
+        let r = panel.dilate(-pad * 0.5);
+        let view = Rect::new_centered(self.player_pos.x, self.player_pos.y, MINIMAP_RANGE, MINIMAP_RANGE);
+        outputs.draw_texture.push((r, view, self.map_texture, 3.05));
+        self.map_markers(outputs, r, view, 3.1);
+    }
+
+    // drag to pan, scroll to zoom about the mouse
+    fn full_map(&mut self, inputs: &FrameInputs, outputs: &mut FrameOutputs) {
+        let sr = inputs.screen_rect;
+        if inputs.lmb == KeyStatus::Pressed || inputs.mmb == KeyStatus::Pressed {
+            let world_per_screen = self.map_view.h / sr.h;
+            self.map_view = self.map_view.translate(-1.0 * inputs.mouse_delta * world_per_screen);
+        }
+        if inputs.scroll_delta != 0.0 {
+            let anchor = inputs.mouse_pos.transform(sr, self.map_view);
+            let h = (self.map_view.h * 0.9f32.powf(inputs.scroll_delta)).clamp(MAP_ZOOM_MIN, MAP_ZOOM_MAX);
+            let k = h / self.map_view.h;
+            self.map_view = Rect::new(anchor.x - (anchor.x - self.map_view.x) * k, anchor.y - (anchor.y - self.map_view.y) * k, self.map_view.w * k, h);
+        }
+
+        outputs.canvas.put_rect(sr, 2.8, Vec4::new(0.0, 0.0, 0.0, 0.92));
+        outputs.draw_texture.push((sr, self.map_view, self.map_texture, 2.85));
+        self.map_markers(outputs, sr, self.map_view, 2.9);
+
+        let mut ui = Ui::new(inputs, outputs, &mut self.ui_state, 3.0);
+        let ch = ui.theme.char_h;
+        ui.label(Rect::new(sr.x, sr.bot() - ch * 1.5, sr.w, ch), &format!("Floor {}, {}   drag to pan, scroll to zoom, M to close", self.l.floor, palette_for_floor(self.l.floor).name), TextAlign::Center, ui.theme.text_dim);
+        ui.finish();
+    }
+
+    pub fn player_name(&self) -> &'static str {
+        PLAYER_NAMES[khash(self.seed.wrapping_mul(1231247)) as usize % PLAYER_NAMES.len()]
+    }
+
+    pub fn hud(&mut self, inputs: &FrameInputs, outputs: &mut FrameOutputs) {
+        let name = self.player_name();
+        let warmups = [self.item_warmup(0), self.item_warmup(1)];
+        let mut ui = Ui::new(inputs, outputs, &mut self.ui_state, 3.0);
+        let ch = ui.theme.char_h;
+        let pad = ch * 0.5;
+
+        let panel = Rect::new(pad, 1.0 - ch * 10.0 - pad, ch * 16.0, ch * 10.0);
+        ui.panel(panel);
+        let inner = panel.dilate(-pad);
+        let mut y = inner.y;
+        let mut row = |h: f32| {
+            let r = Rect::new(inner.x, y, inner.w, h);
+            y += h + pad * 0.5;
+            r
+        };
+
+        let title = row(ch);
+        ui.label(title, name, TextAlign::Left, ui.theme.text);
+        ui.label(title, &format!("Floor {}", self.l.floor), TextAlign::Right, ui.theme.text_dim);
+        ui.label(row(ch), PLAYER_CLASS, TextAlign::Left, ui.theme.text_dim);
+
+        let hp_rect = row(ch);
+        ui.progress_bar(hp_rect, self.player_hp, Vec4::new(0.8, 0.0, 0.0, 1.0));
+        ui.label(hp_rect, &format!("{:.0}/100", (self.player_hp * 100.0).max(0.0)), TextAlign::Center, ui.theme.text);
+        ui.tooltip_if_hovered(hp_rect, "Health. Refilled when you take the stairs down.");
+
+        let fuel_rect = row(ch);
+        ui.progress_bar(fuel_rect, self.player_fuel, FUEL_COLOUR);
+        ui.label(fuel_rect, &format!("Oil {:.0}%", self.player_fuel * 100.0), TextAlign::Center, ui.theme.text);
+        ui.tooltip_if_hovered(fuel_rect, "Lamp oil. Burns down over time and faster when you use Holy Light or the Bible. Pick up oil flasks to refill. When it runs out the dark closes in and shades come for you.");
+
+        ui.label(row(ch), &format!("XP {}", self.player_xp), TextAlign::Left, ui.theme.accent);
+
+        // abilities: (key, name, warmup fraction, in use, description)
+        let abilities = [
+            ("LMB", "Holy Light", warmups[0], self.player_item_on[0], "Holy Light. Hold to burn the first enemy in the beam."),
+            ("RMB", "Bible", warmups[1], self.player_item_on[1], "Bible. Hold to orbit two bibles that damage whatever they touch. They take a couple of seconds to swing out to full reach, and each press reverses the spin."),
+        ];
+        let slots = row(ch * 3.0);
+        for (i, (key, ability_name, warmup, in_use, desc)) in abilities.iter().enumerate() {
+            let r = Rect::new(slots.x + i as f32 * (slots.h + pad), slots.y, slots.h, slots.h);
+            ui.cooldown_slot(r, key, *warmup, *in_use);
+            ui.label(Rect::new(r.x, r.bot() - ch * 0.8, r.w, ch * 0.6), ability_name, TextAlign::Center, ui.theme.text_dim);
+            ui.tooltip_if_hovered(r, desc);
+        }
+
+        ui.finish();
+    }
+}
+
+#[cfg(test)]
+fn test_game(seed: u32) -> Game {
+    let mut g = Game::new(Level::new(seed, 0), seed);
+    g.advance_level();
+    g
+}
+
+// nothing pressed and the mouse in the middle, so the camera sits on the player
+#[cfg(test)]
+fn test_inputs(a: f32) -> FrameInputs {
+    let mut inputs = FrameInputs::new(a);
+    inputs.seed = 1234;
+    inputs.dt = 1.0 / 60.0;
+    inputs.t = inputs.dt;
+    inputs.frame = 1;
+    inputs.mouse_pos = inputs.screen_rect.centroid();
+    inputs
+}
+
+#[cfg(test)]
+fn test_frame_with(g: &mut Game, inputs: &FrameInputs) -> FrameOutputs {
+    let mut outputs = FrameOutputs::new(inputs.screen_rect.aspect());
+    g.frame(inputs, &mut outputs);
+    outputs
+}
+
+#[cfg(test)]
+fn test_frame(g: &mut Game, a: f32) -> FrameOutputs {
+    test_frame_with(g, &test_inputs(a))
+}
+
+// copy of what a frame draws minus anything using the textures in drop, for rendering with and without something
+#[cfg(test)]
+fn frame_without(outputs: &FrameOutputs, a: f32, drop: &[TextureHandle], overlay: bool) -> FrameOutputs {
+    let mut f = FrameOutputs::new(a);
+    f.canvas.buf = outputs.canvas.buf.clone();
+    if overlay {
+        f.overlay.buf = outputs.overlay.buf.clone();
+    }
+    f.draw_texture = outputs.draw_texture.iter().filter(|d| !drop.contains(&d.2)).cloned().collect();
+    f.sprites = outputs.sprites.iter().filter(|b| !drop.contains(&b.atlas)).cloned().collect();
+    f
+}
+
+// mean of r + g + b over the pixels within r of p, p and r in screen space
+#[cfg(test)]
+fn brightness_near(im: &ImageBufferA, a: f32, p: Vec2, r: f32) -> f32 {
+    let mut acc = 0.0;
+    let mut n = 0;
+    for j in 0..im.h {
+        for i in 0..im.w {
+            let q = Vec2::new((i as f32 + 0.5) / im.w as f32 * a, (j as f32 + 0.5) / im.h as f32);
+            if q.dist(p) < r {
+                let px = im.get_px(i, j);
+                acc += (px.0 as f32 + px.1 as f32 + px.2 as f32) / 255.0;
+                n += 1;
+            }
+        }
+    }
+    acc / n as f32
+}
+
+#[test]
+fn test_game_frame() {
+    use crate::renderers::software_renderer::*;
+    let a = 1.0;
+    let mut g = test_game(1);
+    let outputs = test_frame(&mut g, a);
+    let mut sr = SoftwareRenderer::new("font.png");
+    let im = sr.render(&outputs, a, 256, 256);
+
+    // the level around the player shows through the darkness
+    let p = g.camera.world_to_screen(g.player_pos);
+    let r = g.camera.world_len_to_screen(VISION_RANGE * 0.5);
+    let lit = brightness_near(&im, a, p, r);
+    assert!(lit > 0.1, "frame is black around the player: {}", lit);
+
+    // and its the level texture doing it, not just the canvas
+    let without = brightness_near(&sr.draw(&frame_without(&outputs, a, &[g.level_texture], true), a, 256, 256), a, p, r);
+    assert!(lit > without + 0.05, "level texture doesnt show: {} vs {}", lit, without);
+
+    assert_matches_golden(&im, "golden/game_frame.png", 1.0);
+}
+
+#[test]
+fn test_game_sprites() {
+    use crate::renderers::software_renderer::*;
+    let a = 1.0;
+    let mut g = test_game(1);
+    let outputs = test_frame(&mut g, a);
+
+    // altar, fuel and torches all go in one batch
+    assert_eq!(outputs.sprites.len(), 1);
+    assert_eq!(outputs.sprites[0].len(), 1 + g.fuel_pickups.len() + g.l.torches.len());
+
+    // and the ones on screen show up over the level, with the darkness left off so the far ones count too
+    // (clear of the hud and the minimap)
+    let mut sr = SoftwareRenderer::new("font.png");
+    sr.update_textures(&outputs);
+    let with = sr.draw(&frame_without(&outputs, a, &[], false), a, 256, 256);
+    let without = sr.draw(&frame_without(&outputs, a, &[g.atlas_texture], false), a, 256, 256);
+    let on_screen: Vec<Vec2> = std::iter::once(g.l.altar).chain(g.fuel_pickups.iter().cloned()).chain(g.l.torches.iter().cloned())
+        .map(|p| g.camera.world_to_screen(p))
+        .filter(|p| p.x > 0.05 && p.x < a - 0.3 && p.y > 0.05 && p.y < 0.75)
+        .collect();
+    assert!(!on_screen.is_empty());
+    for p in on_screen {
+        let r = g.camera.world_len_to_screen(0.002);
+        assert!((brightness_near(&with, a, p, r) - brightness_near(&without, a, p, r)).abs() > 0.1, "sprite at {:?} doesnt show", p);
+    }
+
+    // two bibles however many enemies there are
+    for n in [0, 5] {
+        g.clear_enemies();
+        for i in 0..n {
+            g.spawn_enemy(0, 1.0, g.player_pos + Vec2::new(0.01 * i as f32, 0.0), Vec2::zero(), i);
+        }
+        let mut outputs = FrameOutputs::new(a);
+        g.do_item(&FrameInputs::new(a), &mut outputs, 1, 1.0 / 60.0, true, false);
+        assert_eq!(outputs.sprites.iter().map(|b| b.len()).sum::<usize>(), 2);
+    }
+}
+
+#[test]
+fn test_game_lighting() {
+    use crate::renderers::software_renderer::*;
+    let a = 1.0;
+    let mut g = test_game(1);
+    let outputs = test_frame(&mut g, a);
+    let mut sr = SoftwareRenderer::new("font.png");
+    sr.update_textures(&outputs);
+
+    // darkness left off so its just the light map doing the shading
+    let lit = sr.draw(&frame_without(&outputs, a, &[], false), a, 256, 256);
+    let unlit = sr.draw(&frame_without(&outputs, a, &[g.light_texture], false), a, 256, 256);
+    let p = g.camera.world_to_screen(g.player_pos);
+    let near = brightness_near(&lit, a, p, g.camera.world_len_to_screen(0.015)) / brightness_near(&unlit, a, p, g.camera.world_len_to_screen(0.015));
+    let whole = brightness_near(&lit, a, p, 2.0) / brightness_near(&unlit, a, p, 2.0);
+    assert!(image_difference(&lit, &unlit) > 2.0, "light map doesnt change the frame");
+    // brightest around the lamp
+    assert!(near > 1.2 && near > whole + 0.2, "lamp doesnt light the player up: {} vs {}", near, whole);
+}
+
+#[test]
+fn test_game_close() {
+    let a = 1.0;
+    let mut g = test_game(1);
+    let frame = test_frame(&mut g, a);
+    assert!(frame.set_texture.len() >= 4);
+    // everything it ever uploaded gets freed when its replaced
+    let mut outputs = FrameOutputs::new(a);
+    g.on_close(&mut outputs);
+    for (_, handle, _) in frame.set_texture.iter() {
+        assert!(outputs.free_texture.contains(handle), "{:?} leaks", handle);
+    }
+}
+
+#[test]
+fn test_game_items() {
+    let a = 1.0;
+    let mut g = test_game(1);
+    let mut inputs = test_inputs(a);
+    let mut press = |g: &mut Game, lmb: KeyStatus, rmb: KeyStatus, mouse: Vec2| {
+        inputs.lmb = lmb;
+        inputs.rmb = rmb;
+        inputs.mouse_pos = mouse;
+        test_frame_with(g, &inputs);
+    };
+    let mid = Vec2::new(a / 2.0, 0.5);
+    use KeyStatus::*;
+
+    // bible is out while held and the hud fills in as it grows out to its orbit
+    press(&mut g, Released, JustPressed, mid);
+    assert!(g.player_item_on[1]);
+    assert!(g.item_warmup(1) > 0.9);
+    for _ in 0..150 {
+        press(&mut g, Released, Pressed, mid);
+    }
+    assert!(g.player_item_on[1]);
+    assert_eq!(g.item_warmup(1), 0.0);
+    // and can go straight back out after being put away
+    press(&mut g, Released, Released, mid);
+    assert!(!g.player_item_on[1]);
+    assert_eq!(g.item_warmup(1), 0.0);
+    press(&mut g, Released, JustPressed, mid);
+    assert!(g.player_item_on[1]);
+    press(&mut g, Released, Released, mid);
+
+    // the laser goes when clicking the world but not when clicking the hud, even if the mouse is dragged off it
+    let hud = Vec2::new(0.05, 0.95);
+    press(&mut g, Released, Released, hud);
+    press(&mut g, JustPressed, Released, hud);
+    press(&mut g, Pressed, Released, hud);
+    press(&mut g, Pressed, Released, mid);
+    assert!(!g.player_item_on[0]);
+    press(&mut g, JustReleased, Released, mid);
+    press(&mut g, Released, Released, mid);
+    press(&mut g, JustPressed, Released, mid);
+    press(&mut g, Pressed, Released, mid);
+    assert!(g.player_item_on[0]);
+}
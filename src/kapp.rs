@@ -2,10 +2,15 @@ use crate::root_scene::*;
 
 use crate::renderers::font_rendering::*;
 use crate::renderers::simple_renderer::*;
+use crate::renderers::sprite_renderer::*;
+use crate::renderers::software_renderer::*;
 use crate::texture_buffer::*;
 use crate::kmath::*;
 use crate::video::*;
 use crate::audio::*;
+use crate::sounds::*;
+use std::sync::Arc;
+use crate::dsp::*;
 
 use std::collections::HashSet;
 use std::time::{SystemTime, Instant, Duration};
@@ -82,10 +87,16 @@ impl FrameInputs {
 
 pub struct FrameOutputs {
     pub canvas: SimpleCanvas,
-    pub set_texture: Vec<(TextureBuffer, usize)>,
-    pub draw_texture: Vec<(Rect, usize, f32)>,
+    pub set_texture: Vec<(Arc<TextureBuffer>, TextureHandle, TextureOptions)>,   // shared so the screenshotter can keep them without copying
+    pub update_texture: Vec<(TextureBuffer, TextureHandle, usize, usize)>,
+    pub free_texture: Vec<TextureHandle>,
+    pub draw_texture: Vec<(Rect, Rect, TextureHandle, f32)>,   // screen rect, uv rect, texture, depth
+    pub sprites: Vec<SpriteBatch>,
+    pub overlay: SimpleCanvas,      // drawn after the textures and sprites, for anything that has to shade them
     pub glyphs: GlyphBuffer,
     pub sounds: Vec<SoundCommand>,
+    pub listener: Option<Listener>,
+    pub bus: Option<BusConfig>,
 }
 
 impl FrameOutputs {
@@ -94,8 +105,14 @@ impl FrameOutputs {
             glyphs: GlyphBuffer::new(),
             canvas: SimpleCanvas::new(a),
             set_texture: Vec::new(),
+            update_texture: Vec::new(),
+            free_texture: Vec::new(),
             draw_texture: Vec::new(),
+            sprites: Vec::new(),
+            overlay: SimpleCanvas::new(a),
             sounds: Vec::new(),
+            listener: None,
+            bus: None,
         }
     }
 }
@@ -103,7 +120,9 @@ impl FrameOutputs {
 pub struct Application {
     video: Video,
     audio: Audio,
+    _samples: Vec<Arc<Sample>>,
     root_scene: RootScene,
+    screenshotter: SoftwareRenderer,
 
     t_last: Instant,
     instant_mouse_pos: Vec2,
@@ -118,14 +137,18 @@ impl Application {
         let video = Video::new("CataCleanser", xres as f32, yres as f32, event_loop);
 
 
-        
+        let mut audio = Audio::new();
+        let samples = load_samples(&mut audio);
+
         let app = Application {
             video,
             root_scene: RootScene::default(),
             t_last: Instant::now(),
             instant_mouse_pos: Vec2::zero(),
             current: FrameInputs::new(xres as f32 / yres as f32),      
-            audio: Audio::new(),     
+            audio,
+            _samples: samples,
+            screenshotter: SoftwareRenderer::new("font.png"),
         };
         app
     }
@@ -224,9 +247,21 @@ impl Application {
 
                 let mut new_outputs = FrameOutputs::new(state.screen_rect.aspect());
                 self.root_scene.frame(&state, &mut new_outputs);
+                if let Some(l) = new_outputs.listener {
+                    self.audio.set_listener(l);
+                }
+                if let Some(bc) = new_outputs.bus {
+                    self.audio.set_bus(bc);
+                }
                 for sc in new_outputs.sounds.iter() {
                     self.audio.handle_command(*sc);
                 }
+                self.screenshotter.update_textures(&new_outputs);
+                if state.key_pressed(VirtualKeyCode::F12) {
+                    let im = self.screenshotter.draw(&new_outputs, state.screen_rect.aspect(), self.video.xres as usize, self.video.yres as usize);
+                    im.dump_to_file("screenshot.png");
+                    println!("saved screenshot.png");
+                }
                 self.video.render(&new_outputs, state.screen_rect.aspect());
             },
             _ => {},
@@ -234,6 +269,7 @@ impl Application {
     }
 
     pub fn exit(&mut self) {
+        self.root_scene.on_exit();
         println!("exiting");
         std::process::exit(0);
     }
@@ -241,6 +277,9 @@ impl Application {
 
 pub trait Demo {
     fn frame(&mut self, inputs: &FrameInputs, outputs: &mut FrameOutputs);
+    fn on_exit(&mut self) {}
+    // about to be replaced by another scene, give back anything the renderer is holding for it
+    fn on_close(&mut self, _outputs: &mut FrameOutputs) {}
 }
 
 pub fn init_demo<T: Demo + Default + 'static>() -> Box<dyn Demo> {
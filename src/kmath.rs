@@ -63,8 +63,8 @@ pub fn chance(seed: u32, percent: f32) -> bool {
 }
 
 pub fn noise1d(t: f32, seed: u32) -> f32 {
-    let hstart = kuniform(seed + 489172373 * t.floor() as u32, 0.0, 1.0);
-    let hend = kuniform(seed + 489172373 * (t.floor() + 1.0) as u32, 0.0, 1.0);
+    let hstart = kuniform(seed.wrapping_add(489172373u32.wrapping_mul(t.floor() as u32)), 0.0, 1.0);
+    let hend = kuniform(seed.wrapping_add(489172373u32.wrapping_mul((t.floor() + 1.0) as u32)), 0.0, 1.0);
     lerp(hstart, hend, smoothstep(t.fract()))
 }
 
@@ -25,6 +25,8 @@ pub struct Level {
     pub grid_type: Vec<u32>,
     pub stairs_up: Vec2,
     pub stairs_down: Vec2,
+    pub altar: Vec2,
+    pub torches: Vec<Vec2>,
 
     pub distances: Vec<f32>,
     pub walldirs: Vec<Vec2>,
@@ -95,7 +97,23 @@ impl Level {
         self.grid_type[furthest_y * self.w + furthest_x] = 3;
         (self.stairs_down.x, self.stairs_down.y) = self.cell_xy(furthest_x, furthest_y);
         
-        self.gen_distances()
+        self.gen_distances();
+
+        // first open site gets the altar, the rest get a torch
+        self.torches = vec![];
+        self.altar = self.stairs_up;
+        let mut have_altar = false;
+        for (i, j, _) in candidates.iter() {
+            if self.grid_type[j * self.w + i] != ENEMY_SITE { continue; }
+            let (x, y) = self.cell_xy(*i, *j);
+            if !self.point(x, y).walkable { continue; }
+            if have_altar {
+                self.torches.push(Vec2::new(x, y));
+            } else {
+                self.altar = Vec2::new(x, y);
+                have_altar = true;
+            }
+        }
     }
 
     pub fn gen_distances(&mut self) {
@@ -274,16 +292,24 @@ impl Level {
 
 impl Default for Level {
     fn default() -> Self {
+        Level::new(SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or(Duration::from_nanos(34123123)).subsec_nanos(), 0)
+    }
+}
+
+impl Level {
+    pub fn new(seed: u32, floor: i32) -> Level {
         let w = 8;
         let h = 8;
         let mut l = Level {
-            seed: SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or(Duration::from_nanos(34123123)).subsec_nanos(),
+            seed,
             w,
             h,
-            floor: 0,
+            floor,
             grid_type: vec![],
             stairs_up: Vec2::zero(),
             stairs_down: Vec2::zero(),
+            altar: Vec2::zero(),
+            torches: vec![],
             distances: vec![],
             walldirs: vec![],
             dw: 0,
@@ -0,0 +1,245 @@
+use crate::kmath::*;
+use crate::level::*;
+use crate::texture_buffer::*;
+
+// paints the level texture off the wall sdf instead of a flat walkable / not walkable
+// floor side uses the levels own wall_distance and wall_dir, wall side gets a chamfer distance to the nearest floor computed here
+// edges are anti aliased over two distance field texels, floor darkens near walls (fake ao), walls get a rim lit from the top left,
+// and moss and grass creep out from the walls based on noise
+// row 0 of the buffer is the bottom of the level, same as before
+
+const AO_RANGE: f32 = 0.012;
+const AO_STRENGTH: f32 = 0.55;
+const RIM_WIDTH: f32 = 0.003;
+const MOSS_RANGE: f32 = 0.02;
+const GRASS_RANGE: f32 = 0.008;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Palette {
+    pub name: &'static str,
+    pub floor: Vec4,
+    pub wall: Vec4,
+    pub rim: Vec4,
+    pub moss: Vec4,
+    pub grass: Vec4,
+    pub moss_amount: f32,   // 0..1, how far the moss gets out from the walls
+    pub grass_amount: f32,
+}
+
+// two floors each, deeper is later
+pub const PALETTES: [Palette; 6] = [
+    Palette {
+        name: "Mossy Crypt",
+        floor: Vec4::new(0.10, 0.12, 0.10, 1.0),
+        wall: Vec4::new(0.33, 0.36, 0.30, 1.0),
+        rim: Vec4::new(0.55, 0.60, 0.50, 1.0),
+        moss: Vec4::new(0.12, 0.25, 0.08, 1.0),
+        grass: Vec4::new(0.22, 0.38, 0.10, 1.0),
+        moss_amount: 0.8,
+        grass_amount: 0.7,
+    },
+    Palette {
+        name: "Sunken Cistern",
+        floor: Vec4::new(0.07, 0.10, 0.14, 1.0),
+        wall: Vec4::new(0.24, 0.32, 0.40, 1.0),
+        rim: Vec4::new(0.50, 0.65, 0.75, 1.0),
+        moss: Vec4::new(0.05, 0.20, 0.18, 1.0),
+        grass: Vec4::new(0.10, 0.30, 0.25, 1.0),
+        moss_amount: 0.6,
+        grass_amount: 0.3,
+    },
+    Palette {
+        name: "Bone Ossuary",
+        floor: Vec4::new(0.14, 0.12, 0.10, 1.0),
+        wall: Vec4::new(0.45, 0.40, 0.32, 1.0),
+        rim: Vec4::new(0.75, 0.70, 0.60, 1.0),
+        moss: Vec4::new(0.25, 0.22, 0.12, 1.0),
+        grass: Vec4::new(0.35, 0.30, 0.18, 1.0),
+        moss_amount: 0.4,
+        grass_amount: 0.2,
+    },
+    Palette {
+        name: "Rust Mines",
+        floor: Vec4::new(0.13, 0.08, 0.06, 1.0),
+        wall: Vec4::new(0.42, 0.24, 0.15, 1.0),
+        rim: Vec4::new(0.70, 0.45, 0.30, 1.0),
+        moss: Vec4::new(0.30, 0.18, 0.05, 1.0),
+        grass: Vec4::new(0.40, 0.30, 0.10, 1.0),
+        moss_amount: 0.4,
+        grass_amount: 0.2,
+    },
+    Palette {
+        name: "Ember Hollow",
+        floor: Vec4::new(0.12, 0.05, 0.04, 1.0),
+        wall: Vec4::new(0.38, 0.14, 0.08, 1.0),
+        rim: Vec4::new(0.90, 0.40, 0.15, 1.0),
+        moss: Vec4::new(0.35, 0.08, 0.02, 1.0),
+        grass: Vec4::new(0.60, 0.20, 0.05, 1.0),
+        moss_amount: 0.5,
+        grass_amount: 0.3,
+    },
+    Palette {
+        name: "The Abyss",
+        floor: Vec4::new(0.05, 0.04, 0.08, 1.0),
+        wall: Vec4::new(0.20, 0.15, 0.30, 1.0),
+        rim: Vec4::new(0.50, 0.35, 0.70, 1.0),
+        moss: Vec4::new(0.15, 0.05, 0.25, 1.0),
+        grass: Vec4::new(0.30, 0.10, 0.40, 1.0),
+        moss_amount: 0.7,
+        grass_amount: 0.4,
+    },
+];
+
+pub fn palette_for_floor(floor: i32) -> &'static Palette {
+    &PALETTES[((floor - 1).max(0) as usize / 2).min(PALETTES.len() - 1)]
+}
+
+// distance from each wall cell to the nearest floor cell, 0 on the floor
+// two pass chamfer is plenty for shading
+fn wall_depth_field(l: &Level) -> Vec<f32> {
+    let (w, h) = (l.dw, l.dh);
+    let a = 1.0 / w as f32;
+    let b = SQRT_2 / w as f32;
+    let mut d: Vec<f32> = l.distances.iter().map(|x| if *x > 0.0 { 0.0 } else { 1.0 }).collect();
+    for j in 0..h {
+        for i in 0..w {
+            let idx = j * w + i;
+            let mut m = d[idx];
+            if i > 0 { m = m.min(d[idx - 1] + a); }
+            if j > 0 {
+                m = m.min(d[idx - w] + a);
+                if i > 0 { m = m.min(d[idx - w - 1] + b); }
+                if i < w - 1 { m = m.min(d[idx - w + 1] + b); }
+            }
+            d[idx] = m;
+        }
+    }
+    for j in (0..h).rev() {
+        for i in (0..w).rev() {
+            let idx = j * w + i;
+            let mut m = d[idx];
+            if i < w - 1 { m = m.min(d[idx + 1] + a); }
+            if j < h - 1 {
+                m = m.min(d[idx + w] + a);
+                if i < w - 1 { m = m.min(d[idx + w + 1] + b); }
+                if i > 0 { m = m.min(d[idx + w - 1] + b); }
+            }
+            d[idx] = m;
+        }
+    }
+    d
+}
+
+// bilinear, samples are at (i / (w-1), j / (h-1)) on the same grid as the levels fields
+fn sample(field: &[f32], w: usize, h: usize, p: Vec2) -> f32 {
+    let xf = ((w - 1) as f32 * p.x).clamp(0.0, (w - 1) as f32);
+    let yf = ((h - 1) as f32 * p.y).clamp(0.0, (h - 1) as f32);
+    let i = (xf.floor() as usize).min(w - 2);
+    let j = (yf.floor() as usize).min(h - 2);
+    let (fx, fy) = (xf - i as f32, yf - j as f32);
+    let top = lerp(field[j * w + i], field[j * w + i + 1], fx);
+    let bot = lerp(field[(j + 1) * w + i], field[(j + 1) * w + i + 1], fx);
+    lerp(top, bot, fy)
+}
+
+fn clamp01(x: f32) -> f32 {
+    x.clamp(0.0, 1.0)
+}
+
+pub fn level_texture(l: &Level, palette: &Palette, w: usize, h: usize) -> TextureBuffer {
+    let depth = wall_depth_field(l);
+    let texel = 1.0 / l.dw as f32;
+    let to_light = Vec2::new(-1.0, -1.0).normalize();
+    let s = l.seed;
+
+    let mut tb = TextureBuffer::new(w, h);
+    for j in 0..h {
+        for i in 0..w {
+            let p = Vec2::new((0.5 + i as f32) / w as f32, (0.5 + j as f32) / h as f32);
+            // wall_distance is the min over the cells corners so it steps once per texel, two texels leaves room for a blend
+            let d_floor = l.wall_distance(p);
+            let d_wall = sample(&depth, l.dw, l.dh, p);
+            let coverage = smoothstep(clamp01(d_floor / (2.0 * texel)));
+
+            // floor: grain, ao, then moss and grass growing out from the walls
+            let grain = 0.85 + 0.3 * noise2d(p.x * 96.0, p.y * 96.0, s.wrapping_mul(1923417));
+            let ao = 1.0 - AO_STRENGTH * (1.0 - clamp01(d_floor / AO_RANGE)).powi(2);
+            let mut floor = palette.floor * (grain * ao);
+            let near = 1.0 - clamp01(d_floor / MOSS_RANGE);
+            let patchy = 0.6 * noise2d(p.x * 40.0, p.y * 40.0, s.wrapping_mul(7134121)) + 0.4 * noise2d(p.x * 160.0, p.y * 160.0, s.wrapping_mul(3312479));
+            let moss = smoothstep(clamp01((patchy + near * palette.moss_amount - 1.0) * 4.0));
+            floor = floor.lerp(palette.moss * ao, moss);
+            let near_grass = 1.0 - clamp01(d_floor / GRASS_RANGE);
+            let blades = noise2d(p.x * 700.0, p.y * 700.0, s.wrapping_mul(918273));
+            let grass = clamp01((blades - 1.0 + near_grass * palette.grass_amount) * 6.0) * moss.max(0.3);
+            floor = floor.lerp(palette.grass, grass);
+
+            // wall: rough stone, lit rim where it faces the light
+            let stone = 0.8 + 0.35 * noise2d(p.x * 48.0, p.y * 48.0, s.wrapping_mul(5512347));
+            // normal points into the wall, on the floor side thats just wall_dir
+            // the levels fields are all 0 inside walls so wall_dir has nothing there, the chamfer gradient stands in
+            let normal = if d_floor > 0.0 {
+                l.wall_dir(p)
+            } else {
+                Vec2::new(
+                    sample(&depth, l.dw, l.dh, p + Vec2::new(texel, 0.0)) - sample(&depth, l.dw, l.dh, p - Vec2::new(texel, 0.0)),
+                    sample(&depth, l.dw, l.dh, p + Vec2::new(0.0, texel)) - sample(&depth, l.dw, l.dh, p - Vec2::new(0.0, texel)),
+                )
+            };
+            let facing = if normal.magnitude() > 0.0 { 0.5 - 0.5 * normal.normalize().dot(to_light) } else { 0.5 };
+            let rim = (1.0 - clamp01(d_wall / RIM_WIDTH)) * facing;
+            let wall = (palette.wall * stone).lerp(palette.rim, rim);
+
+            let c = wall.lerp(floor, coverage);
+            tb.set(i as i32, (h - j - 1) as i32, Vec4::new(clamp01(c.x), clamp01(c.y), clamp01(c.z), 1.0));
+        }
+    }
+    tb
+}
+
+#[test]
+fn test_level_texture() {
+    assert_eq!(palette_for_floor(0).name, PALETTES[0].name);
+    assert_eq!(palette_for_floor(2).name, PALETTES[0].name);
+    assert_eq!(palette_for_floor(3).name, PALETTES[1].name);
+    assert_eq!(palette_for_floor(100).name, PALETTES[PALETTES.len() - 1].name);
+
+    let l = Level::new(1234, 1);
+    let depth = wall_depth_field(&l);
+    // zero exactly on the floor, growing into the walls
+    assert!(depth.iter().zip(l.distances.iter()).all(|(dw, df)| (*df > 0.0) == (*dw == 0.0)));
+    assert!(depth.iter().any(|d| *d > 0.005));
+
+    // moss and grass off so its just the ao and the edge blend being looked at
+    let palette = Palette { moss_amount: 0.0, grass_amount: 0.0, ..PALETTES[0] };
+    let (w, h) = (400, 400);
+    let tb = level_texture(&l, &palette, w, h);
+    assert_eq!(tb.buf.len(), w * h * 4);
+    let texel = 1.0 / l.dw as f32;
+    let mut open = Vec::new();
+    let mut near_wall = Vec::new();
+    let mut wall = Vec::new();
+    let mut edge = Vec::new();
+    for j in 0..h {
+        for i in 0..w {
+            let p = Vec2::new((0.5 + i as f32) / w as f32, (0.5 + j as f32) / h as f32);
+            let c = tb.get(i as i32, (h - j - 1) as i32);
+            let lum = c.x + c.y + c.z;
+            let d = l.wall_distance(p);
+            if d > AO_RANGE * 2.0 { open.push(lum); }
+            if d > 2.0 * texel && d < AO_RANGE / 2.0 { near_wall.push(lum); }
+            if sample(&depth, l.dw, l.dh, p) > 0.01 { wall.push(lum); }
+            if d > 0.5 * texel && d < 1.5 * texel { edge.push(lum); }
+        }
+    }
+    assert!(!open.is_empty() && !near_wall.is_empty() && !wall.is_empty() && !edge.is_empty());
+    let mean = |v: &[f32]| v.iter().sum::<f32>() / v.len() as f32;
+    let (open, near_wall, wall) = (mean(&open), mean(&near_wall), mean(&wall));
+    // edges land in between the floor beside them and the wall rather than a hard step
+    assert!(open < wall, "floor {} wall {}", open, wall);
+    for e in edge.iter() {
+        assert!(*e > near_wall && *e < wall, "edge {} not between floor {} and wall {}", e, near_wall, wall);
+    }
+    // ao darkens the floor near walls
+    assert!(near_wall < open, "near wall {} open {}", near_wall, open);
+}
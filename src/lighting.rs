@@ -0,0 +1,205 @@
+use crate::kmath::*;
+use crate::texture_buffer::*;
+
+// 2d point lights with soft shadows from the wall sdf
+// computed on the cpu into a small buffer covering the camera, uploaded every frame and drawn with linear filtering so it upsamples smoothly
+// only alpha blending is available so a texel is darkness plus a bit of the light colour, not a true multiply
+
+const SHADOW_EPS: f32 = 0.0002;
+const SHADOW_MAX_STEPS: usize = 48;
+const SHADOW_MIN_STEP: f32 = 0.0005;
+const WALL_GLOW_FALLOFF: f32 = 400.0;   // how quickly light dies off going into a wall, so only the lip is lit
+const FLICKER_SPEED: f32 = 9.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    pub pos: Vec2,
+    pub colour: Vec4,       // rgb, w unused
+    pub radius: f32,
+    pub intensity: f32,
+    pub flicker: f32,       // 0 is steady, 1 can go fully out
+    pub softness: f32,      // k in the soft shadow, lower is a wider penumbra
+    pub seed: u32,
+}
+
+pub const PLAYER_LIGHT: Light = Light {
+    pos: Vec2::zero(),
+    colour: Vec4::new(1.0, 0.85, 0.6, 1.0),
+    radius: 0.1,
+    intensity: 1.0,
+    flicker: 0.08,
+    softness: 12.0,
+    seed: 0,
+};
+
+pub const TORCH_LIGHT: Light = Light {
+    colour: Vec4::new(1.0, 0.55, 0.2, 1.0),
+    radius: 0.07,
+    intensity: 0.9,
+    flicker: 0.3,
+    softness: 8.0,
+    ..PLAYER_LIGHT
+};
+
+pub const ALTAR_LIGHT: Light = Light {
+    colour: Vec4::new(0.55, 0.7, 1.0, 1.0),
+    radius: 0.09,
+    intensity: 0.8,
+    flicker: 0.05,
+    softness: 16.0,
+    ..PLAYER_LIGHT
+};
+
+pub const PROJECTILE_LIGHT: Light = Light {
+    radius: 0.025,
+    intensity: 0.6,
+    flicker: 0.0,
+    softness: 24.0,
+    ..PLAYER_LIGHT
+};
+
+impl Light {
+    pub fn at(self, pos: Vec2, seed: u32) -> Light {
+        Light { pos, seed, ..self }
+    }
+
+    pub fn intensity_at_time(&self, t: f32) -> f32 {
+        if self.flicker == 0.0 {
+            return self.intensity;
+        }
+        let n = 0.7 * noise1d(t * FLICKER_SPEED, self.seed) + 0.3 * noise1d(t * FLICKER_SPEED * 3.1, khash(self.seed));
+        self.intensity * (1.0 - self.flicker * n)
+    }
+}
+
+// sphere trace from the light to p, 1 is fully lit and 0 is fully shadowed
+// the wall p is sitting in (if any) doesnt count so wall faces towards the light still catch it
+pub fn soft_shadow(sdf: &impl Fn(Vec2) -> f32, light: Vec2, p: Vec2, k: f32) -> f32 {
+    let u = p - light;
+    let dist = u.magnitude();
+    if dist < SHADOW_EPS {
+        return 1.0;
+    }
+    let dir = u / dist;
+    let end = dist - (-sdf(p)).max(0.0);
+    let mut res: f32 = 1.0;
+    let mut t = 0.0;
+    for _ in 0..SHADOW_MAX_STEPS {
+        if t >= end - SHADOW_EPS {
+            break;
+        }
+        let h = sdf(light + t * dir);
+        if h < SHADOW_EPS {
+            return 0.0;
+        }
+        // penumbra gets wider the further the receiver is behind the occluder
+        res = res.min(k * h / (dist - t));
+        t += h.max(SHADOW_MIN_STEP);
+    }
+    res.clamp(0.0, 1.0)
+}
+
+pub struct LightMap {
+    pub w: usize,
+    pub h: usize,
+    pub light: Vec<Vec4>,   // accumulated rgb per texel, top row first
+}
+
+impl LightMap {
+    pub fn new(w: usize, h: usize) -> LightMap {
+        LightMap { w, h, light: vec![Vec4::new(0.0, 0.0, 0.0, 1.0); w * h] }
+    }
+
+    // world is the region of the level the buffer covers, same orientation as the camera
+    pub fn compute(&mut self, sdf: &impl Fn(Vec2) -> f32, world: Rect, lights: &[Light], ambient: Vec4, t: f32) {
+        let texel_radius = (world.w / self.w as f32).max(world.h / self.h as f32);
+        let visible: Vec<(Light, f32)> = lights.iter()
+            .filter(|l| world.dilate(l.radius).contains(l.pos))
+            .map(|l| (*l, l.intensity_at_time(t)))
+            .filter(|(_, i)| *i > 0.0)
+            .collect();
+
+        for j in 0..self.h {
+            for i in 0..self.w {
+                let uv = Vec2::new((0.5 + i as f32) / self.w as f32, (0.5 + j as f32) / self.h as f32);
+                let p = uv.transform(Rect::unit(), world);
+                let wall_depth = (-sdf(p)).max(0.0);
+                let mut acc = ambient;
+                for (l, intensity) in visible.iter() {
+                    let d = p.dist(l.pos);
+                    if d >= l.radius {
+                        continue;
+                    }
+                    let falloff = (1.0 - d / l.radius) * (1.0 - d / l.radius);
+                    let shadow = if d < texel_radius { 1.0 } else { soft_shadow(sdf, l.pos, p, l.softness) };
+                    let k = intensity * falloff * shadow * (-wall_depth * WALL_GLOW_FALLOFF).exp();
+                    acc.x += l.colour.x * k;
+                    acc.y += l.colour.y * k;
+                    acc.z += l.colour.z * k;
+                }
+                self.light[j * self.w + i] = acc;
+            }
+        }
+    }
+
+    // alpha is how dark it is, the colour is the lights tint scaled so the blend adds tint * brightness
+    pub fn texture(&self, tint: f32) -> TextureBuffer {
+        let mut tb = TextureBuffer::new(self.w, self.h);
+        for j in 0..self.h {
+            for i in 0..self.w {
+                let c = self.light[j * self.w + i];
+                let lum = c.x.max(c.y).max(c.z).min(1.0);
+                let a = (1.0 - lum) + lum * tint;
+                let hue = if lum > 0.0 { c / c.x.max(c.y).max(c.z) } else { c };
+                let rgb = if a > 0.0 { hue * (lum * tint / a) } else { hue };
+                tb.set(i as i32, (self.h - j - 1) as i32, Vec4::new(rgb.x.min(1.0), rgb.y.min(1.0), rgb.z.min(1.0), a.clamp(0.0, 1.0)));
+            }
+        }
+        tb
+    }
+}
+
+#[test]
+fn test_soft_shadow() {
+    // pillar of radius 0.01 at the origin, light off to the left
+    let sdf = |p: Vec2| p.magnitude() - 0.01;
+    let light = Vec2::new(-0.05, 0.0);
+
+    assert_eq!(soft_shadow(&sdf, light, Vec2::new(0.05, 0.0), 8.0), 0.0);
+    assert_eq!(soft_shadow(&sdf, light, Vec2::new(-0.05, 0.05), 8.0), 1.0);
+    // grazing the edge of the pillar is partly lit
+    let edge = soft_shadow(&sdf, light, Vec2::new(0.05, 0.03), 8.0);
+    assert!(edge > 0.0 && edge < 1.0, "{}", edge);
+    // the side of the pillar facing the light is lit even though its inside the wall
+    assert!(soft_shadow(&sdf, light, Vec2::new(-0.0095, 0.0), 8.0) > 0.9);
+}
+
+#[test]
+fn test_light_map() {
+    let sdf = |p: Vec2| p.magnitude() - 0.01;
+    let lights = [Light { flicker: 0.0, ..TORCH_LIGHT }.at(Vec2::new(-0.05, 0.0), 1)];
+    let world = Rect::new_centered(0.0, 0.0, 0.2, 0.2);
+    let mut lm = LightMap::new(40, 40);
+    let ambient = Vec4::new(0.1, 0.1, 0.1, 1.0);
+    lm.compute(&sdf, world, &lights, ambient, 0.0);
+
+    let at = |x: f32, y: f32| {
+        let uv = Vec2::new(x, y).transform(world, Rect::unit());
+        lm.light[(uv.y * 40.0) as usize * 40 + (uv.x * 40.0) as usize]
+    };
+    // next to the torch is bright and orange, behind the pillar is only ambient
+    let near = at(-0.045, 0.0);
+    assert!(near.x > 0.5 && near.x > near.z);
+    assert_eq!(at(0.03, 0.0).x, ambient.x);
+    assert_eq!(at(0.09, 0.09).x, ambient.x);
+
+    let tb = lm.texture(0.3);
+    assert_eq!(tb.buf.len(), 40 * 40 * 4);
+    // unlit texels are mostly opaque black, lit ones see through
+    let alpha = |x: f32, y: f32| {
+        let uv = Vec2::new(x, y).transform(world, Rect::unit());
+        tb.buf[((39 - (uv.y * 40.0) as usize) * 40 + (uv.x * 40.0) as usize) * 4 + 3]
+    };
+    assert!(alpha(0.09, 0.09) > 200);
+    assert!(alpha(-0.045, 0.0) < alpha(0.09, 0.09));
+}
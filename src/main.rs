@@ -2,6 +2,7 @@ mod kmath;
 mod kimg;
 mod video;
 mod audio;
+mod dsp;
 mod texture_buffer;
 mod renderers;
 mod kapp;
@@ -9,13 +10,37 @@ mod game;
 mod root_scene;
 mod level;
 mod enemy_repo;
+mod enemy_shape;
 mod priority_queue;
 mod distance_field;
+mod visibility;
+mod lighting;
+mod minimap;
+mod camera;
+mod particles;
+mod decals;
+mod sprite_atlas;
+mod sprites;
+mod level_texture;
+mod save;
+mod ui;
+mod sounds;
+mod music;
+mod wav;
 
 use crate::kapp::*;
 
 fn main() {
     std::env::set_var("RUST_BACKTRACE", "1");
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(|s| s.as_str()) == Some("render-sounds") {
+        if let Err(e) = sounds::render_presets_cli(&args[1..]) {
+            println!("couldnt render sounds: {}", e);
+        }
+        return;
+    }
+
     let event_loop = glutin::event_loop::EventLoop::new();
     let mut application = Application::new(&event_loop);
     
@@ -0,0 +1,149 @@
+use crate::kmath::*;
+use crate::level::*;
+use crate::texture_buffer::*;
+use crate::visibility::*;
+
+// explored map memory: a coarse grid over the level (which is the unit square), revealed with the same visibility polygon as the vignette
+// the texture covers the whole level in the same orientation as the level texture, so a world rect can be used directly as the uv rect
+// walls never get inside the polygon so a wall texel counts as explored when it touches explored floor
+
+const FLOOR_COLOUR: Vec4 = Vec4::new(0.25, 0.25, 0.28, 0.9);
+const WALL_COLOUR: Vec4 = Vec4::new(0.75, 0.72, 0.65, 1.0);
+const UNEXPLORED_COLOUR: Vec4 = Vec4::new(0.0, 0.0, 0.0, 0.0);
+
+pub struct ExploredMap {
+    pub w: usize,
+    pub h: usize,
+    walkable: Vec<bool>,
+    explored: Vec<bool>,
+    dirty: Option<(usize, usize, usize, usize)>,    // i0, j0, i1, j1 inclusive
+}
+
+impl ExploredMap {
+    pub fn new(l: &Level, w: usize, h: usize) -> ExploredMap {
+        let mut walkable = vec![false; w * h];
+        for j in 0..h {
+            for i in 0..w {
+                walkable[j * w + i] = l.point((0.5 + i as f32) / w as f32, (0.5 + j as f32) / h as f32).walkable;
+            }
+        }
+        ExploredMap { w, h, walkable, explored: vec![false; w * h], dirty: None }
+    }
+
+    fn cell_centre(&self, i: usize, j: usize) -> Vec2 {
+        Vec2::new((0.5 + i as f32) / self.w as f32, (0.5 + j as f32) / self.h as f32)
+    }
+
+    fn cell(&self, p: Vec2) -> Option<(usize, usize)> {
+        if p.x < 0.0 || p.y < 0.0 || p.x >= 1.0 || p.y >= 1.0 {
+            return None;
+        }
+        Some(((p.x * self.w as f32) as usize, (p.y * self.h as f32) as usize))
+    }
+
+    pub fn is_explored(&self, p: Vec2) -> bool {
+        self.cell(p).map(|(i, j)| self.explored[j * self.w + i]).unwrap_or(false)
+    }
+
+    pub fn reveal(&mut self, vp: &VisibilityPolygon) {
+        let reach = vp.dists.iter().fold(0.0f32, |acc, d| acc.max(*d));
+        let i0 = ((vp.origin.x - reach) * self.w as f32).floor().max(0.0) as usize;
+        let j0 = ((vp.origin.y - reach) * self.h as f32).floor().max(0.0) as usize;
+        let i1 = (((vp.origin.x + reach) * self.w as f32).ceil().max(0.0) as usize).min(self.w - 1);
+        let j1 = (((vp.origin.y + reach) * self.h as f32).ceil().max(0.0) as usize).min(self.h - 1);
+        for j in j0..=j1 {
+            for i in i0..=i1 {
+                let idx = j * self.w + i;
+                if self.explored[idx] || !self.walkable[idx] {
+                    continue;
+                }
+                if vp.contains(self.cell_centre(i, j)) {
+                    self.explored[idx] = true;
+                    self.mark_dirty(i, j);
+                }
+            }
+        }
+    }
+
+    // the neighbours are included because a newly explored floor cell can uncover the walls next to it
+    fn mark_dirty(&mut self, i: usize, j: usize) {
+        let (i0, j0) = (i.saturating_sub(1), j.saturating_sub(1));
+        let (i1, j1) = ((i + 1).min(self.w - 1), (j + 1).min(self.h - 1));
+        self.dirty = Some(match self.dirty {
+            None => (i0, j0, i1, j1),
+            Some((a, b, c, d)) => (a.min(i0), b.min(j0), c.max(i1), d.max(j1)),
+        });
+    }
+
+    fn colour(&self, i: usize, j: usize) -> Vec4 {
+        let idx = j * self.w + i;
+        if self.walkable[idx] {
+            return if self.explored[idx] { FLOOR_COLOUR } else { UNEXPLORED_COLOUR };
+        }
+        for dj in -1..=1 {
+            for di in -1..=1 {
+                let ni = i as i32 + di;
+                let nj = j as i32 + dj;
+                if ni < 0 || nj < 0 || ni >= self.w as i32 || nj >= self.h as i32 {
+                    continue;
+                }
+                if self.explored[nj as usize * self.w + ni as usize] {
+                    return WALL_COLOUR;
+                }
+            }
+        }
+        UNEXPLORED_COLOUR
+    }
+
+    fn patch(&self, i0: usize, j0: usize, i1: usize, j1: usize) -> TextureBuffer {
+        let (pw, ph) = (i1 - i0 + 1, j1 - j0 + 1);
+        let mut tb = TextureBuffer::new(pw, ph);
+        for j in j0..=j1 {
+            for i in i0..=i1 {
+                tb.set((i - i0) as i32, (j1 - j) as i32, self.colour(i, j));
+            }
+        }
+        tb
+    }
+
+    pub fn texture(&mut self) -> TextureBuffer {
+        self.dirty = None;
+        self.patch(0, 0, self.w - 1, self.h - 1)
+    }
+
+    // whatever changed since the last call as a sub image and where it goes in the full texture
+    pub fn take_dirty_patch(&mut self) -> Option<(TextureBuffer, usize, usize)> {
+        let (i0, j0, i1, j1) = self.dirty.take()?;
+        Some((self.patch(i0, j0, i1, j1), i0, self.h - 1 - j1))
+    }
+}
+
+#[test]
+fn test_explored_map() {
+    let l = Level::new(1234, 1);
+    let mut em = ExploredMap::new(&l, 128, 128);
+    assert!(!em.is_explored(l.stairs_up));
+    assert!(em.take_dirty_patch().is_none());
+
+    let vp = VisibilityPolygon::new(&|p| l.wall_distance(p), l.stairs_up, 360, 0.1);
+    em.reveal(&vp);
+    assert!(em.is_explored(l.stairs_up));
+    assert!(!em.is_explored(l.stairs_down));
+
+    // the patch covers everything that was revealed and lands in the flipped texture
+    let (patch, x, y) = em.take_dirty_patch().unwrap();
+    assert!(patch.w <= 128 && patch.h <= 128);
+    assert!(x + patch.w <= 128 && y + patch.h <= 128);
+    let (si, sj) = em.cell(l.stairs_up).unwrap();
+    let (px, py) = (si - x, (127 - sj) - y);
+    assert!(patch.buf[(py * patch.w + px) * 4 + 3] > 0);
+    assert!(em.take_dirty_patch().is_none());
+
+    // revealing the same view again changes nothing
+    em.reveal(&vp);
+    assert!(em.take_dirty_patch().is_none());
+
+    let full = em.texture();
+    assert_eq!(full.buf.len(), 128 * 128 * 4);
+    assert_eq!(full.buf[((127 - sj) * 128 + si) * 4 + 3], (FLOOR_COLOUR.w * 255.0) as u8);
+}
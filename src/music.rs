@@ -0,0 +1,288 @@
+use crate::audio::*;
+use crate::dsp::*;
+use crate::kmath::*;
+use crate::sounds::*;
+
+// ambient score, runs on the game side and just issues SoundCommands
+// drones: held notes per floor, deeper floors get lower and more dissonant, threat opens the filter
+// pulses: a heartbeat that speeds up with threat
+// stingers: the occasional scrape or cluster from off to one side
+// everything is picked by hashing (seed, floor, event number) so a run always sounds the same
+// when the floor changes the old drones are let go with a long release while the new ones fade in, thats the crossfade
+
+pub const MUSIC_ID_BASE: u32 = 100;
+const DRONES: u32 = 3;
+const DRONE_GENERATIONS: u32 = 4;
+
+const DRONE_FADE: f32 = 4.0;
+const THREAT_SMOOTHING: f32 = 1.5;   // seconds
+const THREAT_STEPS: f32 = 8.0;       // drones only get resent when threat crosses a step
+pub const THREAT_ENEMIES: f32 = 6.0; // this many enemies on you is as bad as it gets
+
+const DRONE: SoundDesc = SoundDesc {
+    f: 55.0,
+    n: 6.0,
+    troll: 1.3,
+    ea: DRONE_FADE,
+    ed: 0.0,
+    es: 1.0,
+    er: DRONE_FADE,
+    detune: 6.0,
+    voices: 3.0,
+    amp: -30.0,
+    filter: FilterKind::Low,
+    fc: 180.0,
+    fq: 1.2,
+    release: false,
+    priority: 4,
+    ..SOUND_BASE
+};
+
+const PULSE: SoundDesc = SoundDesc {
+    f: 48.0,
+    n: 3.0,
+    troll: 2.0,
+    ea: 0.005,
+    ed: 0.08,
+    es: 0.3,
+    er: 0.2,
+    amp: -20.0,
+    filter: FilterKind::Low,
+    fc: 120.0,
+    fq: 0.707,
+    fenv: 1.0,
+    priority: 2,
+    ..SOUND_BASE
+};
+
+const SCRAPE: SoundDesc = SoundDesc {
+    f: 700.0,
+    n: 5.0,
+    troll: 0.7,
+    ea: 0.6,
+    ed: 0.8,
+    es: 0.4,
+    er: 1.2,
+    detune: 70.0,
+    voices: 6.0,
+    amp: -30.0,
+    filter: FilterKind::Band,
+    fc: 900.0,
+    fq: 4.0,
+    fenv: 1.0,
+    priority: 1,
+    group: GROUP_STINGER,
+    max_instances: 2,
+    ..SOUND_BASE
+};
+
+const CLUSTER: SoundDesc = SoundDesc {
+    f: 233.0,
+    n: 4.0,
+    troll: 1.5,
+    ea: 1.5,
+    ed: 1.0,
+    es: 0.5,
+    er: 2.5,
+    detune: 100.0,  // a semitone between voices
+    voices: 3.0,
+    amp: -28.0,
+    filter: FilterKind::Low,
+    fc: 500.0,
+    fq: 1.0,
+    fenv: 1.5,
+    priority: 1,
+    group: GROUP_STINGER,
+    max_instances: 2,
+    ..SOUND_BASE
+};
+
+// intervals in semitones over the root, later entries are for deeper floors
+const DRONE_INTERVALS: [[f32; 3]; 4] = [
+    [0.0, 7.0, 12.0],   // fifth and octave
+    [0.0, 7.0, 10.0],   // minor seventh creeping in
+    [0.0, 6.0, 13.0],   // tritone, flat ninth
+    [0.0, 1.0, 6.0],    // semitone grind
+];
+
+fn semitones(f: f32, st: f32) -> f32 {
+    f * 2.0f32.powf(st / 12.0)
+}
+
+pub struct Music {
+    pub seed: u32,
+    pub floor: i32,
+    pub threat: f32,
+    generation: u32,
+    drone_step: i32,
+    t: f32,
+    next_pulse: f32,
+    next_stinger: f32,
+    stinger_count: u32,
+}
+
+impl Music {
+    pub fn new(seed: u32) -> Music {
+        Music {
+            seed,
+            floor: 0,
+            threat: 0.0,
+            generation: 0,
+            drone_step: -1,
+            t: 0.0,
+            next_pulse: 0.0,
+            next_stinger: 0.0,
+            stinger_count: 0,
+        }
+    }
+
+    fn floor_seed(&self) -> u32 {
+        khash(self.seed.wrapping_mul(2412317) ^ khash(self.floor as u32))
+    }
+
+    fn depth(&self) -> f32 {
+        ((self.floor - 1).max(0) as f32 / 8.0).min(1.0)
+    }
+
+    fn drone_id(&self, generation: u32, k: u32) -> u32 {
+        MUSIC_ID_BASE + (generation % DRONE_GENERATIONS) * DRONES + k
+    }
+
+    fn root(&self) -> f32 {
+        // somewhere between A1 and E2, dropping as you go down
+        let st = (krand(self.floor_seed()) * 7.0).floor() - self.depth() * 5.0;
+        semitones(55.0, st)
+    }
+
+    fn drone_desc(&self, k: u32) -> SoundDesc {
+        let intervals = DRONE_INTERVALS[((self.depth() * 3.0).round() as usize).min(DRONE_INTERVALS.len() - 1)];
+        let step = self.drone_step as f32 / THREAT_STEPS;
+        SoundDesc {
+            f: semitones(self.root(), intervals[k as usize]),
+            amp: DRONE.amp - k as f32 * 4.0 + step * 6.0,
+            fc: DRONE.fc * 2.0f32.powf(step * 2.5),
+            detune: DRONE.detune + self.depth() * 10.0,
+            pan: (k as f32 - 1.0) * 0.4,
+            ..DRONE
+        }
+    }
+
+    fn start_drones(&mut self, q: &mut SoundQueue) {
+        for k in 0..DRONES {
+            let id = self.drone_id(self.generation, k);
+            q.start(self.drone_desc(k), id);
+        }
+    }
+
+    pub fn update(&mut self, dt: f32, floor: i32, threat: f32, q: &mut SoundQueue) {
+        self.t += dt;
+
+        if floor != self.floor {
+            // stairs: fade out the old floor while the new one comes in
+            if self.floor != 0 {
+                for k in 0..DRONES {
+                    let id = self.drone_id(self.generation, k);
+                    q.stop(self.drone_desc(k), id);
+                }
+                self.generation += 1;
+            }
+            self.floor = floor;
+            self.drone_step = (self.threat * THREAT_STEPS).floor() as i32;
+            self.start_drones(q);
+            self.next_pulse = self.t;
+            self.stinger_count = 0;
+            self.next_stinger = self.t + self.stinger_gap();
+        }
+
+        let k = if dt > 0.0 { 1.0 - (-dt / THREAT_SMOOTHING).exp() } else { 0.0 };
+        self.threat += (threat.clamp(0.0, 1.0) - self.threat) * k;
+        let step = (self.threat * THREAT_STEPS).floor() as i32;
+        if step != self.drone_step {
+            // resending on the same ids retriggers from the current level so it just glides
+            self.drone_step = step;
+            self.start_drones(q);
+        }
+
+        if self.t >= self.next_pulse {
+            let loudness = (self.threat * 1.5 + self.depth() * 0.5).min(1.0);
+            if loudness > 0.05 {
+                q.play(SoundDesc { amp: PULSE.amp + vol_to_db(loudness), f: PULSE.f * (1.0 - self.depth() * 0.2), ..PULSE });
+            }
+            let bpm = lerp(40.0, 120.0, self.threat);
+            self.next_pulse += 60.0 / bpm;
+            // dont fire a burst of catch up beats after a long pause
+            self.next_pulse = self.next_pulse.max(self.t);
+        }
+
+        if self.t >= self.next_stinger {
+            let s = khash(self.floor_seed() ^ khash(self.stinger_count + 1));
+            let pan = krand(khash(s)) * 1.6 - 0.8;
+            let sd = if chance(s, 0.5 + self.depth() * 0.3) {
+                SoundDesc { f: SCRAPE.f * lerp(0.7, 1.4, krand(s)), pan, ..SCRAPE }
+            } else {
+                SoundDesc { f: semitones(self.root(), 24.0 + (krand(s) * 12.0).floor()), pan, ..CLUSTER }
+            };
+            q.play(sd);
+            self.stinger_count += 1;
+            self.next_stinger = self.t + self.stinger_gap();
+        }
+    }
+
+    // 15 to 40 seconds up top, closer together deeper down and when things are bad
+    fn stinger_gap(&self) -> f32 {
+        let r = krand(khash(self.floor_seed() ^ khash(self.stinger_count * 7 + 3)));
+        lerp(15.0, 40.0, r) * lerp(1.0, 0.5, self.depth()) * lerp(1.0, 0.6, self.threat)
+    }
+}
+
+#[cfg(test)]
+fn run_music(seed: u32, floors: &[(f32, i32)], threat: f32, secs: f32) -> Vec<(f32, SoundCommand)> {
+    let mut m = Music::new(seed);
+    let mut q = SoundQueue::default();
+    let mut out = Vec::new();
+    let dt = 1.0 / 60.0;
+    let mut t = 0.0;
+    while t < secs {
+        let floor = floors.iter().rev().find(|(ft, _)| *ft <= t).unwrap().1;
+        m.update(dt, floor, threat, &mut q);
+        for sc in q.commands.drain(..) {
+            out.push((t, sc));
+        }
+        t += dt;
+    }
+    out
+}
+
+#[test]
+fn test_music_deterministic() {
+    let a = run_music(1234, &[(0.0, 1)], 0.3, 60.0);
+    let b = run_music(1234, &[(0.0, 1)], 0.3, 60.0);
+    assert_eq!(a.len(), b.len());
+    for (x, y) in a.iter().zip(b.iter()) {
+        assert_eq!(x.0, y.0);
+        assert_eq!(x.1.id, y.1.id);
+        assert_eq!(x.1.sd.f, y.1.sd.f);
+    }
+    // at least one stinger in a minute
+    assert!(a.iter().any(|(_, sc)| sc.sd.voices >= 3.0 && sc.sd.release));
+
+    let c = run_music(99, &[(0.0, 1)], 0.3, 60.0);
+    assert!(a.iter().zip(c.iter()).any(|(x, y)| x.0 != y.0 || x.1.sd.f != y.1.sd.f));
+}
+
+#[test]
+fn test_music_threat_and_stairs() {
+    let pulses = |threat: f32| run_music(5, &[(0.0, 3)], threat, 30.0).iter().filter(|(_, sc)| sc.sd.f < 50.0 && sc.sd.release && sc.sd.n == PULSE.n).count();
+    assert!(pulses(1.0) > 2 * pulses(0.2));
+
+    // taking the stairs releases the old drones and starts new ones on different ids
+    let out = run_music(5, &[(0.0, 1), (10.0, 2)], 0.0, 12.0);
+    let at_stairs: Vec<&SoundCommand> = out.iter().filter(|(t, _)| (*t - 10.0).abs() < 0.02).map(|(_, sc)| sc).filter(|sc| sc.id < ONESHOT_ID_BASE).collect();
+    let released: Vec<u32> = at_stairs.iter().filter(|sc| sc.sd.release).map(|sc| sc.id).collect();
+    let started: Vec<u32> = at_stairs.iter().filter(|sc| !sc.sd.release).map(|sc| sc.id).collect();
+    assert_eq!(released.len(), DRONES as usize);
+    assert_eq!(started.len(), DRONES as usize);
+    assert!(released.iter().all(|id| !started.contains(id)));
+    // long release and long attack so they overlap
+    assert!(at_stairs.iter().all(|sc| sc.sd.er >= 2.0 && sc.sd.ea >= 2.0));
+}
@@ -0,0 +1,269 @@
+use crate::kmath::*;
+use crate::renderers::simple_renderer::*;
+
+// pooled particles, structure of arrays like the enemies
+// storage is allocated once up front and dead particles are swap removed, when the pool is full new spawns are dropped
+// emitters are just a desc plus a position and direction, bursts go straight in, continuous ones accumulate fractional spawns over frames
+
+pub const MAX_PARTICLES: usize = 4096;
+
+#[derive(Clone, Copy, Debug)]
+pub struct EmitterDesc {
+    pub rate: f32,          // per second, for continuous emitters
+    pub burst: usize,       // how many in one go, for bursts
+    pub lifetime: f32,
+    pub lifetime_var: f32,  // +- fraction
+    pub speed: f32,
+    pub speed_var: f32,     // +- fraction
+    pub spread: f32,        // half angle of the velocity cone around dir, PI is every direction
+    pub drag: f32,          // fraction of velocity lost per second
+    pub colour_start: Vec4,
+    pub colour_end: Vec4,
+    pub size_start: f32,    // world units, full width
+    pub size_end: f32,
+    pub collide: bool,      // bounce off the level sdf
+    pub bounce: f32,        // velocity kept after a bounce
+}
+
+pub const PARTICLE_BASE: EmitterDesc = EmitterDesc {
+    rate: 0.0,
+    burst: 0,
+    lifetime: 0.5,
+    lifetime_var: 0.3,
+    speed: 0.05,
+    speed_var: 0.5,
+    spread: PI,
+    drag: 3.0,
+    colour_start: Vec4::new(1.0, 1.0, 1.0, 1.0),
+    colour_end: Vec4::new(1.0, 1.0, 1.0, 0.0),
+    size_start: 0.001,
+    size_end: 0.0,
+    collide: false,
+    bounce: 0.4,
+};
+
+// colour_start gets swapped for the enemies colour
+pub const DEATH_BURST: EmitterDesc = EmitterDesc {
+    burst: 24,
+    lifetime: 0.7,
+    speed: 0.06,
+    drag: 4.0,
+    size_start: 0.0015,
+    size_end: 0.0005,
+    collide: true,
+    ..PARTICLE_BASE
+};
+
+pub const LASER_SPARKS: EmitterDesc = EmitterDesc {
+    rate: 120.0,
+    lifetime: 0.2,
+    speed: 0.08,
+    spread: 1.0,
+    drag: 6.0,
+    colour_start: Vec4::new(1.0, 0.9, 0.5, 1.0),
+    colour_end: Vec4::new(1.0, 0.2, 0.0, 0.0),
+    size_start: 0.0008,
+    collide: true,
+    ..PARTICLE_BASE
+};
+
+pub const WALL_SPARKS: EmitterDesc = EmitterDesc {
+    burst: 8,
+    lifetime: 0.25,
+    speed: 0.05,
+    spread: 1.2,
+    drag: 5.0,
+    size_start: 0.0008,
+    collide: true,
+    ..PARTICLE_BASE
+};
+
+pub const DUST: EmitterDesc = EmitterDesc {
+    burst: 3,
+    lifetime: 0.6,
+    speed: 0.008,
+    drag: 2.0,
+    colour_start: Vec4::new(0.6, 0.55, 0.5, 0.35),
+    colour_end: Vec4::new(0.6, 0.55, 0.5, 0.0),
+    size_start: 0.001,
+    size_end: 0.0025,
+    ..PARTICLE_BASE
+};
+
+pub struct Emitter {
+    pub desc: EmitterDesc,
+    pub pos: Vec2,
+    pub dir: Vec2,
+    pub active: bool,
+    acc: f32,
+}
+
+impl Emitter {
+    pub fn new(desc: EmitterDesc) -> Emitter {
+        Emitter { desc, pos: Vec2::zero(), dir: Vec2::new(1.0, 0.0), active: false, acc: 0.0 }
+    }
+}
+
+pub struct Particles {
+    pub pos: Vec<Vec2>,
+    pub v: Vec<Vec2>,
+    pub age: Vec<f32>,
+    pub lifetime: Vec<f32>,
+    pub desc: Vec<EmitterDesc>,
+    seed: u32,
+}
+
+impl Particles {
+    pub fn new(seed: u32) -> Particles {
+        Particles {
+            pos: Vec::with_capacity(MAX_PARTICLES),
+            v: Vec::with_capacity(MAX_PARTICLES),
+            age: Vec::with_capacity(MAX_PARTICLES),
+            lifetime: Vec::with_capacity(MAX_PARTICLES),
+            desc: Vec::with_capacity(MAX_PARTICLES),
+            seed,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pos.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.pos.clear();
+        self.v.clear();
+        self.age.clear();
+        self.lifetime.clear();
+        self.desc.clear();
+    }
+
+    fn rand(&mut self) -> f32 {
+        self.seed = khash(self.seed.wrapping_add(1));
+        krand(self.seed)
+    }
+
+    fn spawn(&mut self, desc: &EmitterDesc, pos: Vec2, dir: Vec2) {
+        if self.len() >= MAX_PARTICLES {
+            return;
+        }
+        let theta = dir.y.atan2(dir.x) + (self.rand() * 2.0 - 1.0) * desc.spread;
+        let speed = desc.speed * (1.0 + (self.rand() * 2.0 - 1.0) * desc.speed_var);
+        let lifetime = desc.lifetime * (1.0 + (self.rand() * 2.0 - 1.0) * desc.lifetime_var);
+        self.pos.push(pos);
+        self.v.push(Vec2::new(theta.cos(), theta.sin()) * speed);
+        self.age.push(0.0);
+        self.lifetime.push(lifetime.max(0.01));
+        self.desc.push(*desc);
+    }
+
+    pub fn burst(&mut self, desc: &EmitterDesc, pos: Vec2, dir: Vec2) {
+        for _ in 0..desc.burst {
+            self.spawn(desc, pos, dir);
+        }
+    }
+
+    pub fn emit(&mut self, e: &mut Emitter, dt: f32) {
+        if !e.active {
+            e.acc = 0.0;
+            return;
+        }
+        e.acc += e.desc.rate * dt;
+        while e.acc >= 1.0 {
+            self.spawn(&e.desc, e.pos, e.dir);
+            e.acc -= 1.0;
+        }
+    }
+
+    // sdf is the distance to the nearest wall and normal points out of it, only used by colliding particles
+    pub fn update(&mut self, dt: f32, sdf: &impl Fn(Vec2) -> f32, normal: &impl Fn(Vec2) -> Vec2) {
+        let mut i = self.len();
+        while i > 0 {
+            i -= 1;
+            self.age[i] += dt;
+            if self.age[i] >= self.lifetime[i] {
+                self.pos.swap_remove(i);
+                self.v.swap_remove(i);
+                self.age.swap_remove(i);
+                self.lifetime.swap_remove(i);
+                self.desc.swap_remove(i);
+                continue;
+            }
+            let d = self.desc[i];
+            self.v[i] = self.v[i] * (1.0 - d.drag * dt).max(0.0);
+            let new_pos = self.pos[i] + self.v[i] * dt;
+            if d.collide && sdf(new_pos) <= 0.0 {
+                let n = normal(self.pos[i]).normalize();
+                let v = self.v[i];
+                self.v[i] = (v - 2.0 * v.dot(n) * n) * d.bounce;
+            } else {
+                self.pos[i] = new_pos;
+            }
+        }
+    }
+
+    // everything goes into the one canvas buffer, so its one draw call however many there are
+    pub fn draw(&self, canvas: &mut SimpleCanvas, to_screen: &impl Fn(Vec2) -> Vec2, world_to_screen_len: f32, depth: f32) {
+        canvas.reserve_triangles(self.len() * 2);
+        for i in 0..self.len() {
+            let d = &self.desc[i];
+            let t = self.age[i] / self.lifetime[i];
+            let size = lerp(d.size_start, d.size_end, t) * world_to_screen_len;
+            if size <= 0.0 {
+                continue;
+            }
+            canvas.put_rect(to_screen(self.pos[i]).rect_centered(size, size), depth, d.colour_start.lerp(d.colour_end, t));
+        }
+    }
+}
+
+#[test]
+fn test_particles() {
+    let open = |_p: Vec2| 1.0;
+    let up = |_p: Vec2| Vec2::new(0.0, 1.0);
+    let mut ps = Particles::new(1);
+
+    // bursts spawn exactly burst many and they all die by the end of their lifetime
+    ps.burst(&DEATH_BURST, Vec2::new(0.5, 0.5), Vec2::new(1.0, 0.0));
+    assert_eq!(ps.len(), DEATH_BURST.burst);
+    let max_life = DEATH_BURST.lifetime * (1.0 + DEATH_BURST.lifetime_var);
+    let mut t = 0.0;
+    while t < max_life + 0.1 {
+        ps.update(0.01, &open, &up);
+        t += 0.01;
+    }
+    assert_eq!(ps.len(), 0);
+
+    // continuous emitters carry the fraction over, 120/s for half a second is 60
+    let mut e = Emitter::new(LASER_SPARKS);
+    e.active = true;
+    for _ in 0..50 {
+        ps.emit(&mut e, 0.01);
+    }
+    assert!((59..=60).contains(&ps.len()), "{}", ps.len());
+    e.active = false;
+    ps.emit(&mut e, 1.0);
+    assert!(ps.len() <= 60);
+
+    // the pool never grows past its capacity
+    for _ in 0..MAX_PARTICLES {
+        ps.burst(&DEATH_BURST, Vec2::new(0.5, 0.5), Vec2::new(1.0, 0.0));
+    }
+    assert_eq!(ps.len(), MAX_PARTICLES);
+    assert_eq!(ps.pos.capacity(), MAX_PARTICLES);
+    ps.clear();
+
+    // colliding particles stay out of the floor at y > 0.5
+    let floor = |p: Vec2| 0.5 - p.y;
+    let desc = EmitterDesc { burst: 50, lifetime: 1.0, lifetime_var: 0.0, speed: 0.5, spread: 0.5, drag: 0.0, collide: true, ..PARTICLE_BASE };
+    ps.burst(&desc, Vec2::new(0.5, 0.45), Vec2::new(0.0, 1.0));
+    for _ in 0..50 {
+        ps.update(0.01, &floor, &|_p: Vec2| Vec2::new(0.0, -1.0));
+        assert!(ps.pos.iter().all(|p| p.y <= 0.5));
+    }
+    // and bounced back up
+    assert!(ps.v.iter().all(|v| v.y <= 0.0));
+
+    let mut canvas = SimpleCanvas::new(1.0);
+    ps.draw(&mut canvas, &|p: Vec2| p, 1.0, 1.7);
+    assert_eq!(canvas.buf.len(), 50 * 2 * 3 * 7 * 4);
+}
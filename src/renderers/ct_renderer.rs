@@ -127,7 +127,7 @@ impl CTRenderer {
 
 pub struct CTCanvas {
     a: f32,
-    buf: Vec<u8>,
+    pub buf: Vec<u8>,
 }
 
 impl CTCanvas {
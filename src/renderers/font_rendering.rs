@@ -1,40 +1,112 @@
 use crate::kmath::*;
 use crate::renderers::ct_renderer::*;
 
-pub fn glyph_buffer_to_canvas(buf: &GlyphBuffer, a: f32) -> CTCanvas {
-    let clip_fn = |mut c: u8| {
-        if c >= 'a' as u8 && c <= 'z' as u8 {
-            c -= 'a' as u8 - 'A' as u8;
-        }
-        if c >= '-' as u8 && c <= '_' as u8 {
-            let x = c - '-' as u8;
-            let w = '_' as u8 - '-' as u8 + 1; // maybe +1
-            Some(Rect::new(0.0, 0.0, 1.0, 1.0).grid_child(x as i32, 0, w as i32, 1))
-        } else {
-            None
-        }
-    };
-
-    // aspect should be 14/12
+// font.png is printable ascii from ' ' to '~' in a 16x6 grid of 12x14 glyphs
+pub const FONT_ASPECT: f32 = 12.0 / 14.0;
+const FONT_COLS: u32 = 16;
+const FONT_ROWS: u32 = 6;
 
-    // old font clip fn
-    // let clip_fn = |c: u8| {
-    //     let idx = c - ' ' as u8;
-    //     let w = '@' as u8 - ' ' as u8;
-    //     let x = idx % w;
-    //     let y = idx / w;
-    //     Rect::new(0.0, 0.0, 1.0, 1.0).grid_child(x as i32, y as i32, w as i32, 3)
-    // };
+pub fn glyph_uv(c: char) -> Option<Rect> {
+    // space is blank so no point drawing it
+    if !(c > ' ' && c <= '~') {
+        return None;
+    }
+    let idx = c as u32 - ' ' as u32;
+    Some(Rect::unit().grid_child((idx % FONT_COLS) as i32, (idx / FONT_COLS) as i32, FONT_COLS as i32, FONT_ROWS as i32))
+}
 
+pub fn glyph_buffer_to_canvas(buf: &GlyphBuffer, a: f32) -> CTCanvas {
     let mut c = CTCanvas::new(a);
     for g in &buf.buf {
-        if let Some(r_uv) = clip_fn(g.0 as u8) {
+        if let Some(r_uv) = glyph_uv(g.0) {
             c.put_rect(g.1, r_uv, g.2, g.3);
         }
     }
     c
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TextLayout {
+    pub char_h: f32,
+    pub char_a: f32,        // glyph w / h
+    pub line_spacing: f32,  // distance between lines as a multiple of char_h
+    pub align: TextAlign,
+}
+
+impl TextLayout {
+    pub fn new(char_h: f32) -> TextLayout {
+        TextLayout {
+            char_h,
+            char_a: FONT_ASPECT,
+            line_spacing: 1.3,
+            align: TextAlign::Left,
+        }
+    }
+    pub fn align(mut self, align: TextAlign) -> TextLayout {
+        self.align = align;
+        self
+    }
+    pub fn line_spacing(mut self, line_spacing: f32) -> TextLayout {
+        self.line_spacing = line_spacing;
+        self
+    }
+    pub fn char_w(&self) -> f32 {
+        self.char_h * self.char_a
+    }
+
+    // greedy word wrap to max_w. explicit newlines are kept and words too long for a line get split
+    pub fn wrap(&self, s: &str, max_w: f32) -> Vec<String> {
+        let max_chars = ((max_w / self.char_w()) + 0.001).floor().max(1.0) as usize;
+        let mut lines = Vec::new();
+        for paragraph in s.split('\n') {
+            let mut line = String::new();
+            for word in paragraph.split_whitespace() {
+                let mut word: Vec<char> = word.chars().collect();
+                let line_len = line.chars().count();
+                if line_len > 0 && line_len + 1 + word.len() > max_chars {
+                    lines.push(std::mem::take(&mut line));
+                }
+                while word.len() > max_chars {
+                    let rest = word.split_off(max_chars);
+                    if !line.is_empty() {
+                        lines.push(std::mem::take(&mut line));
+                    }
+                    lines.push(word.into_iter().collect());
+                    word = rest;
+                }
+                if !line.is_empty() {
+                    line.push(' ');
+                }
+                line.extend(word);
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    // (w, h) the wrapped text takes up
+    pub fn measure(&self, s: &str, max_w: f32) -> (f32, f32) {
+        let lines = self.wrap(s, max_w);
+        let w = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0) as f32 * self.char_w();
+        (w, self.lines_h(lines.len()))
+    }
+
+    fn lines_h(&self, n: usize) -> f32 {
+        if n == 0 {
+            0.0
+        } else {
+            self.char_h + (n - 1) as f32 * self.char_h * self.line_spacing
+        }
+    }
+}
+
 pub struct GlyphBuffer {
     pub buf: Vec<(char, Rect, f32, Vec4)>,
 }
@@ -81,4 +153,60 @@ impl GlyphBuffer {
         r.x += r.w / 2.0;
         self.pushl(r, s, a, c, d);
     }
-}
\ No newline at end of file
+
+    // wraps s inside r, returns the rect the text actually covers
+    pub fn push_layout(&mut self, r: Rect, s: &str, layout: &TextLayout, d: f32, colour: Vec4) -> Rect {
+        let lines = layout.wrap(s, r.w);
+        let cw = layout.char_w();
+        let mut extent: Option<Rect> = None;
+        for (i, line) in lines.iter().enumerate() {
+            let line_w = line.chars().count() as f32 * cw;
+            let x = match layout.align {
+                TextAlign::Left => r.x,
+                TextAlign::Center => r.x + (r.w - line_w) / 2.0,
+                TextAlign::Right => r.right() - line_w,
+            };
+            let y = r.y + i as f32 * layout.char_h * layout.line_spacing;
+            self.push_str(line, x, y, cw, layout.char_h, d, colour);
+            let line_rect = Rect::new(x, y, line_w, layout.char_h);
+            extent = Some(match extent {
+                None => line_rect,
+                Some(e) => {
+                    let x0 = e.x.min(line_rect.x);
+                    let x1 = e.right().max(line_rect.right());
+                    Rect::new(x0, e.y, x1 - x0, line_rect.bot() - e.y)
+                },
+            });
+        }
+        extent.unwrap_or(Rect::new(r.x, r.y, 0.0, 0.0))
+    }
+}
+
+#[test]
+fn test_text_layout() {
+    let layout = TextLayout::new(0.1).line_spacing(1.5);
+    let cw = layout.char_w();
+
+    // 10 chars per line
+    let lines = layout.wrap("the temperature plummets and you begin to shiver", cw * 10.0);
+    assert_eq!(lines, vec!["the", "temperatur", "e plummets", "and you", "begin to", "shiver"]);
+    for l in lines.iter() {
+        assert!(l.chars().count() <= 10);
+    }
+
+    assert_eq!(layout.wrap("a b\n\nc", 1.0), vec!["a b", "", "c"]);
+
+    let (w, h) = layout.measure("you feel a creeping dread", cw * 12.0);
+    assert!((w - cw * 10.0).abs() < 0.0001);  // "you feel a" / "creeping" / "dread"
+    assert!((h - (0.1 + 2.0 * 0.15)).abs() < 0.0001);
+
+    let mut gb = GlyphBuffer::new();
+    let r = Rect::new(0.0, 0.0, cw * 10.0, 1.0);
+    let extent = gb.push_layout(r, "ab\nabcd", &layout.align(TextAlign::Right), 1.0, Vec4::grey(1.0));
+    assert_eq!(gb.buf.len(), 6);
+    assert!((gb.buf[0].1.x - cw * 8.0).abs() < 0.0001);
+    assert!((gb.buf[2].1.x - cw * 6.0).abs() < 0.0001);
+    assert!((extent.w - cw * 4.0).abs() < 0.0001);
+    assert!((extent.h - 0.25).abs() < 0.0001);
+}
+
@@ -1,4 +1,6 @@
 pub mod ct_renderer;
 pub mod texture_renderer;
 pub mod simple_renderer;
-pub mod font_rendering;
\ No newline at end of file
+pub mod sprite_renderer;
+pub mod font_rendering;
+pub mod software_renderer;
\ No newline at end of file
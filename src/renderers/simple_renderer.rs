@@ -102,7 +102,7 @@ impl SimpleRenderer {
 
 pub struct SimpleCanvas {
     a: f32,
-    buf: Vec<u8>,
+    pub buf: Vec<u8>,
 }
 
 impl SimpleCanvas {
@@ -113,6 +113,11 @@ impl SimpleCanvas {
         }
     }
 
+    // for callers about to put a lot of triangles in at once
+    pub fn reserve_triangles(&mut self, n: usize) {
+        self.buf.reserve(n * 3 * 7 * 4);
+    }
+
     fn put_float(&mut self, x: f32) {
         for b in (x as f32).to_le_bytes() {
             self.buf.push(b);
@@ -120,29 +125,19 @@ impl SimpleCanvas {
     }
 
     pub fn put_triangle(&mut self, p1: Vec2, p2: Vec2, p3: Vec2, depth: f32, colour: Vec4) {
-        self.put_float(p1.x/self.a);
-        self.put_float(p1.y);
-        self.put_float(depth);
-        self.put_float(colour.x);
-        self.put_float(colour.y);
-        self.put_float(colour.z);
-        self.put_float(colour.w);
-
-        self.put_float(p2.x/self.a);
-        self.put_float(p2.y);
-        self.put_float(depth);
-        self.put_float(colour.x);
-        self.put_float(colour.y);
-        self.put_float(colour.z);
-        self.put_float(colour.w);
-
-        self.put_float(p3.x/self.a);
-        self.put_float(p3.y);
-        self.put_float(depth);
-        self.put_float(colour.x);
-        self.put_float(colour.y);
-        self.put_float(colour.z);
-        self.put_float(colour.w);
+        self.put_triangle_gradient(p1, p2, p3, depth, colour, colour, colour);
+    }
+    // colours are per vertex and get interpolated across the triangle
+    pub fn put_triangle_gradient(&mut self, p1: Vec2, p2: Vec2, p3: Vec2, depth: f32, c1: Vec4, c2: Vec4, c3: Vec4) {
+        for (p, c) in [(p1, c1), (p2, c2), (p3, c3)] {
+            self.put_float(p.x/self.a);
+            self.put_float(p.y);
+            self.put_float(depth);
+            self.put_float(c.x);
+            self.put_float(c.y);
+            self.put_float(c.z);
+            self.put_float(c.w);
+        }
     }
     pub fn put_poly(&mut self, center: Vec2, radius: f32, n_sides: i32, depth: f32, colour: Vec4) {
         for i in 0..n_sides {
@@ -163,6 +158,10 @@ impl SimpleCanvas {
         self.put_triangle(a,b,c, depth, colour);
         self.put_triangle(b,d,c, depth, colour);
     }
+    pub fn put_quad_gradient(&mut self, a: Vec2, b: Vec2, c: Vec2, d: Vec2, depth: f32, ca: Vec4, cb: Vec4, cc: Vec4, cd: Vec4) {
+        self.put_triangle_gradient(a,b,c, depth, ca, cb, cc);
+        self.put_triangle_gradient(b,d,c, depth, cb, cd, cc);
+    }
     pub fn put_line(&mut self, a: Vec2, b: Vec2, w: f32, depth: f32, colour: Vec4) {
         let v = (b - a).normalize();
         let wv = w/2.0 * Vec2::new(-v.y, v.x);
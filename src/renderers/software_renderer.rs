@@ -0,0 +1,433 @@
+use crate::kapp::*;
+use crate::kimg::*;
+use crate::kmath::*;
+use crate::texture_buffer::*;
+use crate::renderers::font_rendering::*;
+use crate::renderers::simple_renderer::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// CPU version of Video::render so we can take screenshots and do golden image tests without a GPU
+// mirrors what the shaders do: same projection, GL_LESS depth test with depth writes, SRC_ALPHA ONE_MINUS_SRC_ALPHA blending,
+// and the same texture filtering and wrapping
+
+pub struct SoftwareRenderer {
+    w: usize,
+    h: usize,
+    colour: Vec<Vec4>,
+    depth: Vec<f32>,
+    textures: HashMap<TextureHandle, (Arc<TextureBuffer>, TextureOptions)>,
+    font: ImageBufferA,
+}
+
+struct Vertex {
+    p: Vec2,
+    z: f32,
+    colour: Vec4,
+    uv: Vec2,
+}
+
+enum Sampler<'a> {
+    Untextured,
+    Texture(&'a TextureBuffer, TextureOptions),
+    Image(&'a ImageBufferA),
+}
+
+impl<'a> Sampler<'a> {
+    fn sample(&self, uv: Vec2) -> Vec4 {
+        match self {
+            Sampler::Untextured => Vec4::new(1.0, 1.0, 1.0, 1.0),
+            Sampler::Texture(tb, opts) => {
+                let texel = |i: i32, j: i32| {
+                    let i = wrap_texel(i, tb.w, opts.wrap);
+                    let j = wrap_texel(j, tb.h, opts.wrap);
+                    let idx = (j * tb.w + i) * 4;
+                    Vec4::new(tb.buf[idx] as f32 / 255.0, tb.buf[idx + 1] as f32 / 255.0, tb.buf[idx + 2] as f32 / 255.0, tb.buf[idx + 3] as f32 / 255.0)
+                };
+                match opts.filter {
+                    TextureFilter::Nearest => texel((uv.x * tb.w as f32).floor() as i32, (uv.y * tb.h as f32).floor() as i32),
+                    TextureFilter::Linear => {
+                        let (i, fx) = floorfrac(uv.x * tb.w as f32 - 0.5);
+                        let (j, fy) = floorfrac(uv.y * tb.h as f32 - 0.5);
+                        let (i, j) = (i as i32, j as i32);
+                        let top = texel(i, j).lerp(texel(i + 1, j), fx);
+                        let bot = texel(i, j + 1).lerp(texel(i + 1, j + 1), fx);
+                        top.lerp(bot, fy)
+                    },
+                }
+            },
+            Sampler::Image(im) => {
+                // uploaded with bytes() so row 0 is v = 0
+                let (i, j) = nearest_texel(uv, im.w, im.h);
+                let px = im.get_px(i, j);
+                Vec4::new(px.0 as f32 / 255.0, px.1 as f32 / 255.0, px.2 as f32 / 255.0, px.3 as f32 / 255.0)
+            },
+        }
+    }
+}
+
+fn nearest_texel(uv: Vec2, w: usize, h: usize) -> (usize, usize) {
+    let i = ((uv.x * w as f32).floor() as i32).clamp(0, w as i32 - 1);
+    let j = ((uv.y * h as f32).floor() as i32).clamp(0, h as i32 - 1);
+    (i as usize, j as usize)
+}
+
+fn wrap_texel(i: i32, n: usize, wrap: TextureWrap) -> usize {
+    let n = n as i32;
+    let i = match wrap {
+        TextureWrap::Clamp => i.clamp(0, n - 1),
+        TextureWrap::Repeat => i.rem_euclid(n),
+    };
+    i as usize
+}
+
+fn read_floats(buf: &[u8]) -> Vec<f32> {
+    buf.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
+}
+
+// the projection in the shaders maps depth d to ndc z = 1 - 0.001 d
+fn depth_to_ndc(depth: f32) -> f32 {
+    1.0 - 0.001 * depth
+}
+
+// evaluated with the endpoints in a fixed order so both triangles sharing an edge get bit identical values,
+// otherwise rounding leaves cracks along quad diagonals
+fn edge(a: Vec2, b: Vec2, p: Vec2) -> f32 {
+    if (a.x, a.y) > (b.x, b.y) {
+        return -edge(b, a, p);
+    }
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+// top left rule so shared edges dont get blended twice
+fn is_top_left(a: Vec2, b: Vec2) -> bool {
+    let d = b - a;
+    (d.y == 0.0 && d.x > 0.0) || d.y < 0.0
+}
+
+impl SoftwareRenderer {
+    pub fn new(font_path: &str) -> SoftwareRenderer {
+        SoftwareRenderer {
+            w: 0,
+            h: 0,
+            colour: Vec::new(),
+            depth: Vec::new(),
+            textures: HashMap::new(),
+            font: ImageBufferA::new_from_file(font_path).unwrap(),
+        }
+    }
+
+    // textures persist between frames like on the gpu, so this has to see every frame even if we only draw some
+    // set_texture buffers are shared rather than copied, a patch only copies one if the frame still holds it too
+    pub fn update_textures(&mut self, outputs: &FrameOutputs) {
+        for handle in &outputs.free_texture {
+            self.textures.remove(handle);
+        }
+        for (buf, handle, opts) in &outputs.set_texture {
+            self.textures.insert(*handle, (buf.clone(), *opts));
+        }
+        for (buf, handle, x, y) in &outputs.update_texture {
+            if let Some((tb, _)) = self.textures.get_mut(handle) {
+                let tb = Arc::make_mut(tb);
+                for j in 0..buf.h.min(tb.h.saturating_sub(*y)) {
+                    for i in 0..buf.w.min(tb.w.saturating_sub(*x)) {
+                        let src = (j * buf.w + i) * 4;
+                        let dst = ((j + y) * tb.w + i + x) * 4;
+                        tb.buf[dst..dst + 4].copy_from_slice(&buf.buf[src..src + 4]);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn render(&mut self, outputs: &FrameOutputs, a: f32, w: usize, h: usize) -> ImageBufferA {
+        self.update_textures(outputs);
+        self.draw(outputs, a, w, h)
+    }
+
+    pub fn draw(&mut self, outputs: &FrameOutputs, a: f32, w: usize, h: usize) -> ImageBufferA {
+        self.w = w;
+        self.h = h;
+        self.colour.clear();
+        self.colour.resize(w*h, Vec4::new(0.0, 0.0, 0.0, 1.0));
+        self.depth.clear();
+        self.depth.resize(w*h, 1.0);
+
+        self.draw_canvas(&outputs.canvas);
+
+        // textured quads, same verts and uvs as TextureRenderer::render
+        let textures = std::mem::take(&mut self.textures);
+        for (r, uv, handle, depth) in &outputs.draw_texture {
+            let (tex, opts) = match textures.get(handle) {
+                Some(t) => t,
+                None => continue,
+            };
+            let white = Vec4::new(1.0, 1.0, 1.0, 1.0);
+            let v = |p: Vec2, uv: Vec2| Vertex { p: Vec2::new(p.x / a, p.y), z: *depth, colour: white, uv };
+            let sampler = Sampler::Texture(tex.as_ref(), *opts);
+            let (uv_tl, uv_tr, uv_bl, uv_br) = (Vec2::new(uv.left(), uv.bot()), Vec2::new(uv.right(), uv.bot()), Vec2::new(uv.left(), uv.top()), Vec2::new(uv.right(), uv.top()));
+            self.raster_triangle([v(r.tl(), uv_tl), v(r.tr(), uv_tr), v(r.bl(), uv_bl)], &sampler);
+            self.raster_triangle([v(r.tr(), uv_tr), v(r.br(), uv_br), v(r.bl(), uv_bl)], &sampler);
+        }
+
+        // sprite batches: pos3 colour4 uv2, colour is the tint
+        for batch in &outputs.sprites {
+            let (tex, opts) = match textures.get(&batch.atlas) {
+                Some(t) => t,
+                None => continue,
+            };
+            let floats = read_floats(&batch.buf);
+            for tri in floats.chunks_exact(9*3) {
+                let vert = |k: usize| {
+                    let f = &tri[k*9..k*9 + 9];
+                    Vertex { p: Vec2::new(f[0], f[1]), z: f[2], colour: Vec4::new(f[3], f[4], f[5], f[6]), uv: Vec2::new(f[7], f[8]) }
+                };
+                self.raster_triangle([vert(0), vert(1), vert(2)], &Sampler::Texture(tex.as_ref(), *opts));
+            }
+        }
+        self.textures = textures;
+
+        self.draw_canvas(&outputs.overlay);
+
+        // glyphs: pos3 colour4 uv2
+        let font_ct_canvas = glyph_buffer_to_canvas(&outputs.glyphs, a);
+        let floats = read_floats(&font_ct_canvas.buf);
+        let font = std::mem::replace(&mut self.font, ImageBufferA::new(0, 0));
+        for tri in floats.chunks_exact(9*3) {
+            let vert = |k: usize| {
+                let f = &tri[k*9..k*9 + 9];
+                Vertex { p: Vec2::new(f[0], f[1]), z: f[2], colour: Vec4::new(f[3], f[4], f[5], f[6]), uv: Vec2::new(f[7], f[8]) }
+            };
+            self.raster_triangle([vert(0), vert(1), vert(2)], &Sampler::Image(&font));
+        }
+        self.font = font;
+
+        // window is opaque so thats what a screenshot should be too
+        let mut im = ImageBufferA::new(self.w, self.h);
+        for j in 0..self.h {
+            for i in 0..self.w {
+                let c = self.colour[j*self.w + i];
+                let to_u8 = |x: f32| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+                im.set_px(i, j, (to_u8(c.x), to_u8(c.y), to_u8(c.z), 255));
+            }
+        }
+        im
+    }
+
+    // simple canvas: pos3 colour4
+    fn draw_canvas(&mut self, canvas: &SimpleCanvas) {
+        let floats = read_floats(&canvas.buf);
+        for tri in floats.chunks_exact(7*3) {
+            let vert = |k: usize| {
+                let f = &tri[k*7..k*7 + 7];
+                Vertex { p: Vec2::new(f[0], f[1]), z: f[2], colour: Vec4::new(f[3], f[4], f[5], f[6]), uv: Vec2::zero() }
+            };
+            self.raster_triangle([vert(0), vert(1), vert(2)], &Sampler::Untextured);
+        }
+    }
+
+    fn raster_triangle(&mut self, v: [Vertex; 3], sampler: &Sampler) {
+        let z = depth_to_ndc(v[0].z);
+        if !(-1.0..=1.0).contains(&z) {
+            return;
+        }
+
+        // canvas space is 0..1 with y down, pixel centers at (i + 0.5) / w
+        let to_px = |p: Vec2| Vec2::new(p.x * self.w as f32, p.y * self.h as f32);
+        let mut p = [to_px(v[0].p), to_px(v[1].p), to_px(v[2].p)];
+        let mut order = [0, 1, 2];
+        let area = edge(p[0], p[1], p[2]);
+        if area == 0.0 {
+            return;
+        }
+        if area < 0.0 {
+            p.swap(1, 2);
+            order.swap(1, 2);
+        }
+        let area = area.abs();
+
+        let min_x = p[0].x.min(p[1].x).min(p[2].x).floor().max(0.0) as usize;
+        let min_y = p[0].y.min(p[1].y).min(p[2].y).floor().max(0.0) as usize;
+        let max_x = (p[0].x.max(p[1].x).max(p[2].x).ceil() as usize).min(self.w);
+        let max_y = (p[0].y.max(p[1].y).max(p[2].y).ceil() as usize).min(self.h);
+
+        let tl = [is_top_left(p[1], p[2]), is_top_left(p[2], p[0]), is_top_left(p[0], p[1])];
+
+        for j in min_y..max_y {
+            for i in min_x..max_x {
+                let c = Vec2::new(i as f32 + 0.5, j as f32 + 0.5);
+                let w = [edge(p[1], p[2], c), edge(p[2], p[0], c), edge(p[0], p[1], c)];
+                if (0..3).any(|k| w[k] < 0.0 || (w[k] == 0.0 && !tl[k])) {
+                    continue;
+                }
+
+                let idx = j*self.w + i;
+                if z >= self.depth[idx] {
+                    continue;
+                }
+
+                let b = [w[0] / area, w[1] / area, w[2] / area];
+                let mut colour = Vec4::new(0.0, 0.0, 0.0, 0.0);
+                let mut uv = Vec2::zero();
+                for k in 0..3 {
+                    let vk = &v[order[k]];
+                    colour = colour + b[k] * vk.colour;
+                    uv = uv + b[k] * vk.uv;
+                }
+                let t = sampler.sample(uv);
+                let src = Vec4::new(t.x * colour.x, t.y * colour.y, t.z * colour.z, t.w * colour.w);
+
+                self.depth[idx] = z;
+                let dst = self.colour[idx];
+                self.colour[idx] = src * src.w + dst * (1.0 - src.w);
+            }
+        }
+    }
+}
+
+// mean absolute difference per channel, 0..255
+pub fn image_difference(a: &ImageBufferA, b: &ImageBufferA) -> f32 {
+    assert!(a.w == b.w && a.h == b.h, "image sizes differ: {}x{} vs {}x{}", a.w, a.h, b.w, b.h);
+    let mut acc = 0.0;
+    for (pa, pb) in a.pixels.iter().zip(b.pixels.iter()) {
+        acc += (pa.0 as f32 - pb.0 as f32).abs();
+        acc += (pa.1 as f32 - pb.1 as f32).abs();
+        acc += (pa.2 as f32 - pb.2 as f32).abs();
+        acc += (pa.3 as f32 - pb.3 as f32).abs();
+    }
+    acc / (a.pixels.len() * 4) as f32
+}
+
+// compares against a reference png, a missing one is a failure
+// run with BLESS_GOLDEN=1 to write the references after an intentional change, then check the pngs in
+const BLESS_GOLDEN: &str = "BLESS_GOLDEN";
+
+pub fn assert_matches_golden(im: &ImageBufferA, path: &str, tolerance: f32) {
+    if std::env::var_os(BLESS_GOLDEN).is_some() {
+        println!("blessing {}", path);
+        im.dump_to_file(path);
+        return;
+    }
+    match ImageBufferA::new_from_file(path) {
+        Some(reference) => {
+            let diff = image_difference(im, &reference);
+            if diff > tolerance {
+                im.dump_to_file(&format!("{}.actual.png", path));
+                panic!("{} differs from render by {} (tolerance {}), wrote {}.actual.png", path, diff, tolerance, path);
+            }
+        },
+        None => {
+            im.dump_to_file(&format!("{}.actual.png", path));
+            panic!("no reference at {}, wrote {}.actual.png, run with {}=1 to bless it", path, path, BLESS_GOLDEN);
+        },
+    }
+}
+
+#[test]
+fn test_software_renderer() {
+    let a = 1.0;
+    let mut outputs = FrameOutputs::new(a);
+    outputs.canvas.put_rect(Rect::new(0.1, 0.1, 0.5, 0.5), 1.0, Vec4::new(1.0, 0.0, 0.0, 1.0));
+    // lower depth drawn later loses
+    outputs.canvas.put_rect(Rect::new(0.3, 0.3, 0.5, 0.5), 0.5, Vec4::new(0.0, 1.0, 0.0, 1.0));
+    outputs.canvas.put_circle(Vec2::new(0.7, 0.7), 0.15, 1.2, Vec4::new(0.0, 0.0, 1.0, 0.5));
+
+    let mut tb = TextureBuffer::new(2, 2);
+    tb.set(0, 0, Vec4::new(1.0, 1.0, 0.0, 1.0));
+    tb.set(1, 1, Vec4::new(0.0, 1.0, 1.0, 1.0));
+    let tex = TextureHandle::alloc();
+    outputs.set_texture.push((Arc::new(tb), tex, TextureOptions::default()));
+    outputs.draw_texture.push((Rect::new(0.0, 0.8, 0.2, 0.2), Rect::unit(), tex, 2.0));
+
+    outputs.glyphs.push_str("HELLO", 0.05, 0.02, 0.06, 0.07, 3.0, Vec4::new(1.0, 1.0, 1.0, 1.0));
+
+    let mut sr = SoftwareRenderer::new("font.png");
+    let im = sr.render(&outputs, a, 200, 200);
+
+    assert_eq!(im.get_px(60, 60), (255, 0, 0, 255));
+    assert_eq!(im.get_px(110, 110), (255, 0, 0, 255));
+    assert_eq!(im.get_px(150, 100), (0, 255, 0, 255));
+    assert_eq!(im.get_px(199, 0), (0, 0, 0, 255));
+    // half alpha blue over green
+    let (r, g, b, _) = im.get_px(140, 140);
+    assert!(r == 0 && (g as i32 - 128).abs() <= 1 && (b as i32 - 128).abs() <= 1);
+    // texture is drawn upside down like the gl path: row 0 is the bottom
+    assert_eq!(im.get_px(10, 190), (255, 255, 0, 255));
+    assert_eq!(im.get_px(30, 170), (0, 255, 255, 255));
+
+    assert_matches_golden(&im, "golden/software_renderer.png", 0.5);
+}
+
+#[test]
+fn test_software_renderer_textures() {
+    let mut sr = SoftwareRenderer::new("font.png");
+    let tex = TextureHandle::alloc();
+
+    let mut outputs = FrameOutputs::new(1.0);
+    let mut tb = TextureBuffer::new(2, 2);
+    tb.set(0, 0, Vec4::new(1.0, 0.0, 0.0, 1.0));
+    outputs.set_texture.push((Arc::new(tb), tex, TextureOptions { filter: TextureFilter::Nearest, wrap: TextureWrap::Repeat }));
+    sr.update_textures(&outputs);
+
+    // next frame only patches one texel and draws the texture tiled twice across
+    let mut outputs = FrameOutputs::new(1.0);
+    let mut patch = TextureBuffer::new(1, 1);
+    patch.set(0, 0, Vec4::new(0.0, 0.0, 1.0, 1.0));
+    outputs.update_texture.push((patch, tex, 1, 1));
+    outputs.draw_texture.push((Rect::unit(), Rect::new(0.0, 0.0, 2.0, 2.0), tex, 1.0));
+    let im = sr.render(&outputs, 1.0, 8, 8);
+
+    // bottom left texel of each tile is row 0 col 0, top right is the patch
+    assert_eq!(im.get_px(0, 7), (255, 0, 0, 255));
+    assert_eq!(im.get_px(4, 3), (255, 0, 0, 255));
+    assert_eq!(im.get_px(2, 4), (0, 0, 255, 255));
+    assert_eq!(im.get_px(7, 0), (0, 0, 255, 255));
+    assert_eq!(im.get_px(2, 7), (0, 0, 0, 255));
+
+    // freed textures stop drawing
+    let mut outputs = FrameOutputs::new(1.0);
+    outputs.free_texture.push(tex);
+    outputs.draw_texture.push((Rect::unit(), Rect::unit(), tex, 1.0));
+    let im = sr.render(&outputs, 1.0, 8, 8);
+    assert_eq!(im.get_px(0, 7), (0, 0, 0, 255));
+}
+
+#[test]
+fn test_software_renderer_sprites() {
+    use crate::renderers::sprite_renderer::*;
+    let mut sr = SoftwareRenderer::new("font.png");
+    let atlas = TextureHandle::alloc();
+
+    // atlas rows are top first: red green on top, blue white underneath
+    let mut tb = TextureBuffer::new(2, 2);
+    tb.set(0, 0, Vec4::new(1.0, 0.0, 0.0, 1.0));
+    tb.set(1, 0, Vec4::new(0.0, 1.0, 0.0, 1.0));
+    tb.set(0, 1, Vec4::new(0.0, 0.0, 1.0, 1.0));
+    tb.set(1, 1, Vec4::new(1.0, 1.0, 1.0, 1.0));
+    let mut outputs = FrameOutputs::new(1.0);
+    outputs.set_texture.push((Arc::new(tb), atlas, TextureOptions { filter: TextureFilter::Nearest, wrap: TextureWrap::Clamp }));
+
+    // upright on the left, upside down and half transparent on the right, one batch
+    let mut batch = SpriteBatch::new(atlas, 1.0);
+    let sprite = Sprite { pos: Vec2::new(0.25, 0.5), size: Vec2::new(0.5, 1.0), rotation: 0.0, uv: Rect::unit(), tint: Vec4::new(1.0, 1.0, 1.0, 1.0), depth: 1.0 };
+    batch.push(&sprite);
+    batch.push(&Sprite { pos: Vec2::new(0.75, 0.5), rotation: PI, tint: Vec4::new(1.0, 1.0, 1.0, 0.5), ..sprite });
+    assert_eq!(batch.len(), 2);
+    outputs.sprites.push(batch);
+    let im = sr.render(&outputs, 1.0, 8, 8);
+
+    assert_eq!(im.get_px(0, 0), (255, 0, 0, 255));
+    assert_eq!(im.get_px(3, 0), (0, 255, 0, 255));
+    assert_eq!(im.get_px(0, 7), (0, 0, 255, 255));
+    assert_eq!(im.get_px(4, 0), (128, 128, 128, 255));
+    assert_eq!(im.get_px(7, 7), (128, 0, 0, 255));
+
+    // a uv sub rect just shows that part of the atlas
+    let mut outputs = FrameOutputs::new(1.0);
+    let mut batch = SpriteBatch::new(atlas, 1.0);
+    batch.push(&Sprite { pos: Vec2::new(0.5, 0.5), size: Vec2::new(1.0, 1.0), uv: Rect::new(0.5, 0.5, 0.5, 0.5), ..sprite });
+    outputs.sprites.push(batch);
+    let im = sr.render(&outputs, 1.0, 8, 8);
+    assert_eq!(im.get_px(0, 0), (255, 255, 255, 255));
+    assert_eq!(im.get_px(7, 7), (255, 255, 255, 255));
+}
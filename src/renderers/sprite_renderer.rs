@@ -0,0 +1,178 @@
+use glow::*;
+use crate::kmath::*;
+use crate::texture_buffer::*;
+
+// many textured quads from one atlas in one draw call
+// a batch is built on the cpu like the canvas, vertices are pos3 colour4 uv2 same as the glyphs, the colour is the tint
+// atlas rows are stored top first like the pngs they came from, so a sprites top edge samples uv.top(),
+// the other way up from draw_texture where row 0 ends up at the bottom
+
+#[derive(Clone, Copy, Debug)]
+pub struct Sprite {
+    pub pos: Vec2,      // centre, screen space
+    pub size: Vec2,     // full width and height
+    pub rotation: f32,  // radians about pos
+    pub uv: Rect,       // region of the atlas
+    pub tint: Vec4,
+    pub depth: f32,
+}
+
+#[derive(Clone)]
+pub struct SpriteBatch {
+    pub atlas: TextureHandle,
+    a: f32,
+    pub buf: Vec<u8>,
+}
+
+impl SpriteBatch {
+    pub fn new(atlas: TextureHandle, a: f32) -> SpriteBatch {
+        SpriteBatch {
+            atlas,
+            a,
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len() / (6 * 9 * 4)
+    }
+
+    fn put_float(&mut self, x: f32) {
+        self.buf.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn put_vert(&mut self, p: Vec2, uv: Vec2, s: &Sprite) {
+        self.put_float(p.x / self.a);
+        self.put_float(p.y);
+        self.put_float(s.depth);
+        self.put_float(s.tint.x);
+        self.put_float(s.tint.y);
+        self.put_float(s.tint.z);
+        self.put_float(s.tint.w);
+        self.put_float(uv.x);
+        self.put_float(uv.y);
+    }
+
+    pub fn push(&mut self, s: &Sprite) {
+        let half = s.size / 2.0;
+        let corner = |x: f32, y: f32| s.pos + Vec2::new(x * half.x, y * half.y).rotate(s.rotation);
+        let (tl, tr, bl, br) = (corner(-1.0, -1.0), corner(1.0, -1.0), corner(-1.0, 1.0), corner(1.0, 1.0));
+        let (uv_tl, uv_tr, uv_bl, uv_br) = (s.uv.tl(), s.uv.tr(), s.uv.bl(), s.uv.br());
+        self.put_vert(tl, uv_tl, s);
+        self.put_vert(tr, uv_tr, s);
+        self.put_vert(bl, uv_bl, s);
+        self.put_vert(bl, uv_bl, s);
+        self.put_vert(tr, uv_tr, s);
+        self.put_vert(br, uv_br, s);
+    }
+}
+
+pub struct SpriteRenderer {
+    vbo: NativeBuffer,
+    vao: NativeVertexArray,
+    program: NativeProgram,
+}
+
+impl SpriteRenderer {
+    pub fn new(gl: &glow::Context) -> SpriteRenderer {
+        unsafe {
+            let vbo = gl.create_buffer().unwrap();
+            gl.bind_buffer(glow::ARRAY_BUFFER, Some(vbo));
+
+            let vao = gl.create_vertex_array().unwrap();
+            gl.bind_vertex_array(Some(vao));
+
+            gl.vertex_attrib_pointer_f32(0, 3, glow::FLOAT, false, 4*4 + 4*3 + 4*2, 0);
+            gl.enable_vertex_attrib_array(0);
+            gl.vertex_attrib_pointer_f32(1, 4, glow::FLOAT, false, 4*4 + 4*3 + 4*2, 4*3);
+            gl.enable_vertex_attrib_array(1);
+            gl.vertex_attrib_pointer_f32(2, 2, glow::FLOAT, false, 4*4 + 4*3 + 4*2, 4*3 + 4*4);
+            gl.enable_vertex_attrib_array(2);
+
+            // Shader
+            let program = gl.create_program().expect("Cannot create program");
+            let vertex_src = r#"
+                #version 330 core
+                layout (location = 0) in vec3 in_pos;
+                layout (location = 1) in vec4 in_colour;
+                layout (location = 2) in vec2 in_uv;
+
+                const mat4 projection = mat4(
+                    2, 0, 0, 0,
+                    0, -2, 0, 0,
+                    0, 0, -0.001, 0,
+                    -1, 1, 1, 1
+                );
+
+                out vec4 vert_colour;
+                out vec2 vert_uv;
+
+                void main() {
+                    vert_colour = in_colour;
+                    vert_uv = in_uv;
+                    gl_Position = projection * vec4(in_pos, 1.0);
+                }
+            "#;
+
+            let vs = gl.create_shader(glow::VERTEX_SHADER).expect("cannot create vertex shader");
+            gl.shader_source(vs, vertex_src);
+            gl.compile_shader(vs);
+            if !gl.get_shader_compile_status(vs) {
+                panic!("{}", gl.get_shader_info_log(vs));
+            }
+            gl.attach_shader(program, vs);
+
+            let fragment_src = r#"
+                #version 330 core
+                in vec4 vert_colour;
+                in vec2 vert_uv;
+
+                out vec4 frag_colour;
+
+                uniform sampler2D tex;
+
+                void main() {
+                    frag_colour = texture(tex, vert_uv) * vert_colour;
+                }
+            "#;
+
+            let fs = gl.create_shader(glow::FRAGMENT_SHADER).expect("cannot create fragment shader");
+            gl.shader_source(fs, fragment_src);
+            gl.compile_shader(fs);
+            if !gl.get_shader_compile_status(fs) {
+                panic!("{}", gl.get_shader_info_log(fs));
+            }
+            gl.attach_shader(program, fs);
+
+            gl.link_program(program);
+            if !gl.get_program_link_status(program) {
+                panic!("{}", gl.get_program_info_log(program));
+            }
+            gl.detach_shader(program, fs);
+            gl.delete_shader(fs);
+            gl.detach_shader(program, vs);
+            gl.delete_shader(vs);
+
+            SpriteRenderer {
+                vbo,
+                vao,
+                program,
+            }
+        }
+    }
+
+    pub fn render(&self, gl: &glow::Context, batch: &SpriteBatch, texture: NativeTexture) {
+        if batch.len() == 0 {
+            return;
+        }
+        unsafe {
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            gl.use_program(Some(self.program));
+            gl.bind_vertex_array(Some(self.vao));
+            gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.vbo));
+            gl.buffer_data_u8_slice(glow::ARRAY_BUFFER, &batch.buf, glow::DYNAMIC_DRAW);
+            let vert_count = batch.buf.len() / (9*4);
+            gl.draw_arrays(glow::TRIANGLES, 0, vert_count as i32);
+        }
+    }
+}
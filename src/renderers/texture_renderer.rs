@@ -1,13 +1,13 @@
 use crate::kmath::*;
 use crate::texture_buffer::*;
 use glow::*;
+use std::collections::HashMap;
 
-const NUM_TEXTURES: usize = 2;
 pub struct TextureRenderer {
     vbo: NativeBuffer,
     vao: NativeVertexArray,
     program: NativeProgram,
-    textures: [NativeTexture; NUM_TEXTURES],
+    textures: HashMap<TextureHandle, NativeTexture>,
 }
 
 impl TextureRenderer {
@@ -84,37 +84,72 @@ impl TextureRenderer {
             gl.detach_shader(program, vs);
             gl.delete_shader(vs);
 
-            let textures = [gl.create_texture().unwrap(), gl.create_texture().unwrap()];
-            for i in 0..NUM_TEXTURES {
-                gl.bind_texture(glow::TEXTURE_2D, Some(textures[i]));
-                gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, glow::NEAREST as i32);
-                gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, glow::NEAREST as i32);
-                gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE as i32);
-                gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE as i32);
-            }
-            
             TextureRenderer {
                 vbo,
                 vao,
                 program,
-                textures,
+                textures: HashMap::new(),
             }
         }
-        }
+    }
 
-    pub fn update(&self, gl: &glow::Context, buf: &TextureBuffer, texture: usize) {
+    // full upload, allocates the texture the first time a handle is seen
+    pub fn update(&mut self, gl: &glow::Context, buf: &TextureBuffer, handle: TextureHandle, opts: TextureOptions) {
         unsafe {
-            gl.bind_texture(glow::TEXTURE_2D, Some(self.textures[texture]));
+            let texture = *self.textures.entry(handle).or_insert_with(|| gl.create_texture().unwrap());
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            let filter = match opts.filter {
+                TextureFilter::Nearest => glow::NEAREST,
+                TextureFilter::Linear => glow::LINEAR,
+            };
+            let wrap = match opts.wrap {
+                TextureWrap::Clamp => glow::CLAMP_TO_EDGE,
+                TextureWrap::Repeat => glow::REPEAT,
+            };
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, filter as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, filter as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, wrap as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_T, wrap as i32);
             gl.tex_image_2d(glow::TEXTURE_2D, 0, glow::RGBA as i32, 
                 buf.w as i32, 
                 buf.h as i32, 0, RGBA, glow::UNSIGNED_BYTE, 
                 Some(&buf.buf));
+        }
+    }
+
+    // writes buf into an existing texture with its row 0 at texel (x, y)
+    pub fn update_sub(&self, gl: &glow::Context, buf: &TextureBuffer, handle: TextureHandle, x: usize, y: usize) {
+        if let Some(texture) = self.textures.get(&handle) {
+            unsafe {
+                gl.bind_texture(glow::TEXTURE_2D, Some(*texture));
+                gl.tex_sub_image_2d(glow::TEXTURE_2D, 0, x as i32, y as i32,
+                    buf.w as i32,
+                    buf.h as i32, RGBA, glow::UNSIGNED_BYTE,
+                    PixelUnpackData::Slice(&buf.buf));
             }
         }
-        
-        pub fn render(&self, gl: &glow::Context, rect: Rect, a: f32, texture: usize, depth: f32) {
+    }
+
+    pub fn texture(&self, handle: TextureHandle) -> Option<NativeTexture> {
+        self.textures.get(&handle).copied()
+    }
+
+    pub fn free(&mut self, gl: &glow::Context, handle: TextureHandle) {
+        if let Some(texture) = self.textures.remove(&handle) {
             unsafe {
-            gl.bind_texture(glow::TEXTURE_2D, Some(self.textures[texture]));
+                gl.delete_texture(texture);
+            }
+        }
+    }
+
+    // uv is in texture coordinates, v = 0 is row 0 of the buffer which ends up at the bottom of rect
+    pub fn render(&self, gl: &glow::Context, rect: Rect, uv: Rect, a: f32, handle: TextureHandle, depth: f32) {
+        let texture = match self.textures.get(&handle) {
+            Some(texture) => *texture,
+            None => return,
+        };
+        unsafe {
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
             gl.use_program(Some(self.program));
             gl.bind_vertex_array(Some(self.vao));
             gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.vbo));
@@ -123,38 +158,38 @@ impl TextureRenderer {
             buf_floats.push(rect.tl().x/a);
             buf_floats.push(rect.tl().y);
             buf_floats.push(depth);
-            buf_floats.push(0.0);
-            buf_floats.push(1.0);
+            buf_floats.push(uv.left());
+            buf_floats.push(uv.bot());
             
             buf_floats.push(rect.tr().x/a);
             buf_floats.push(rect.tr().y);
             buf_floats.push(depth);
-            buf_floats.push(1.0);
-            buf_floats.push(1.0);
+            buf_floats.push(uv.right());
+            buf_floats.push(uv.bot());
             
             buf_floats.push(rect.bl().x/a);
             buf_floats.push(rect.bl().y);
             buf_floats.push(depth);
-            buf_floats.push(0.0);
-            buf_floats.push(0.0);
+            buf_floats.push(uv.left());
+            buf_floats.push(uv.top());
             
             buf_floats.push(rect.tr().x/a);
             buf_floats.push(rect.tr().y);
             buf_floats.push(depth);
-            buf_floats.push(1.0);
-            buf_floats.push(1.0);
+            buf_floats.push(uv.right());
+            buf_floats.push(uv.bot());
             
             buf_floats.push(rect.br().x/a);
             buf_floats.push(rect.br().y);
             buf_floats.push(depth);
-            buf_floats.push(1.0);
-            buf_floats.push(0.0);
+            buf_floats.push(uv.right());
+            buf_floats.push(uv.top());
             
             buf_floats.push(rect.bl().x/a);
             buf_floats.push(rect.bl().y);
             buf_floats.push(depth);
-            buf_floats.push(0.0);
-            buf_floats.push(0.0);
+            buf_floats.push(uv.left());
+            buf_floats.push(uv.top());
 
             let mut buf = Vec::<u8>::new();
             for f in buf_floats {
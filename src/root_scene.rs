@@ -3,43 +3,114 @@ use crate::kapp::*;
 use crate::kmath::*;
 
 use crate::game::*;
+use crate::save::*;
+use crate::ui::*;
+use crate::renderers::font_rendering::*;
 
 pub struct RootScene {
     curr_scene: Option<Box<dyn Demo>>,
     show_menu: bool,
+    menu_selection: usize,
+    ui_state: UiState,
 }
 
 impl Default for RootScene {
     fn default() -> Self {
         RootScene {
-            curr_scene: Some(init_demo::<Game>()),
-            show_menu: false,
+            curr_scene: None,
+            show_menu: true,
+            menu_selection: 0,
+            ui_state: UiState::default(),
+        }
+    }
+}
+
+impl RootScene {
+    fn menu_items(&self) -> Vec<&'static str> {
+        let mut items = Vec::new();
+        if save_exists(SAVE_PATH) {
+            items.push("Continue");
+        }
+        items.push("New Game");
+        items
+    }
+
+    fn replace_scene(&mut self, scene: Box<dyn Demo>, outputs: &mut FrameOutputs) {
+        if let Some(old) = self.curr_scene.as_mut() {
+            old.on_close(outputs);
+        }
+        self.curr_scene = Some(scene);
+        self.show_menu = false;
+    }
+
+    fn select(&mut self, item: &str, outputs: &mut FrameOutputs) {
+        if item == "Continue" {
+            match SaveData::read_from_file(SAVE_PATH) {
+                Ok(sd) => self.replace_scene(Box::new(Game::from_save_data(&sd)), outputs),
+                Err(e) => {
+                    println!("couldnt load save, deleting it: {}", e);
+                    delete_save(SAVE_PATH);
+                    self.menu_selection = 0;
+                },
+            }
+        } else if item == "New Game" {
+            delete_save(SAVE_PATH);
+            self.replace_scene(init_demo::<Game>(), outputs);
         }
     }
 }
 
 impl Demo for RootScene {
-    fn frame(&mut self, inputs: &FrameInputs, outputs: &mut FrameOutputs) {
+    fn on_exit(&mut self) {
         if let Some(curr) = self.curr_scene.as_mut() {
-            curr.frame(inputs, outputs);
-        } else {
+            curr.on_exit();
+        }
+    }
+
+    fn frame(&mut self, inputs: &FrameInputs, outputs: &mut FrameOutputs) {
+        if !self.show_menu {
+            if let Some(curr) = self.curr_scene.as_mut() {
+                curr.frame(inputs, outputs);
+                return;
+            }
             self.show_menu = true;
         }
 
-        // if inputs.key_rising(VirtualKeyCode::Escape) {
-        //     self.show_menu = !self.show_menu;
-        // }
-        // if self.show_menu {
-        //     let ca = 12.0 / 14.0;
-        //     let ch = 0.02;
-        //     let cw = ch * ca;
-        //     let w = cw * 30.0;
+        let items = self.menu_items();
+        self.menu_selection = self.menu_selection.min(items.len() - 1);
+
+        if inputs.key_pressed(VirtualKeyCode::W) || inputs.key_pressed(VirtualKeyCode::Up) {
+            self.menu_selection = (self.menu_selection + items.len() - 1) % items.len();
+        }
+        if inputs.key_pressed(VirtualKeyCode::S) || inputs.key_pressed(VirtualKeyCode::Down) {
+            self.menu_selection = (self.menu_selection + 1) % items.len();
+        }
 
-        //     let c = Vec4::grey(0.3);
-        //     let wcom = 1.0 - w;
-        //     outputs.canvas.put_rect(inputs.screen_rect.child(wcom/2.0, 0.0, w, 1.0), 4.0, c);
+        let ch = 0.04;
+        let cw = ch * FONT_ASPECT;
+        let x = inputs.screen_rect.centroid().x;
 
-            // outputs.glyphs.push_center_str(self.demo_table[i].0, x, y_initial + i as f32 * ch, cw, ch * 0.8, 5.5, colour);
-        // }
+        outputs.glyphs.push_center_str("CataCleanser", x, 0.3, cw * 1.5, ch * 1.5, 5.5, Vec4::grey(0.9));
+
+        let mut chosen = None;
+        let mut ui = Ui::new(inputs, outputs, &mut self.ui_state, 5.0);
+        ui.theme.char_h = ch;
+        for i in 0..items.len() {
+            let y = 0.5 + i as f32 * ch * 1.5;
+            let item_rect = Rect::new(x - cw * 6.0, y, cw * 12.0, ch * 1.3);
+            if ui.hovered(item_rect) {
+                self.menu_selection = i;
+            }
+            if ui.selectable(item_rect, items[i], i == self.menu_selection) {
+                chosen = Some(i);
+            }
+        }
+        ui.finish();
+        if inputs.key_pressed(VirtualKeyCode::Return) {
+            chosen = Some(self.menu_selection);
+        }
+        if let Some(i) = chosen {
+            self.select(items[i], outputs);
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,310 @@
+use crate::kmath::*;
+use std::fs::File;
+use std::io::{Read, Write};
+
+// save file is a tiny little endian binary blob:
+// magic, version, then whatever that version's layout is
+// when the layout changes bump SAVE_VERSION, keep the old reader around and add a match arm in SaveData::from_bytes
+// so old saves upgrade instead of getting thrown away
+//
+// balance changes: anything derived from a tuning number is stored relative to it where it can be (enemy hp as a fraction
+// of the records initial hp), so retuning needs no migration at all.
+// when a change does alter what a stored value means, eg xp curves getting rescaled or fuel 1.0 meaning a different amount,
+// bump SAVE_VERSION even if the layout is the same and have the old versions read_vN convert the value after read_body.
+// the conversion uses numbers written down in that reader, not the live constants, which will have moved on again by then
+
+pub const SAVE_PATH: &str = "cata.sav";
+pub const SAVE_MAGIC: [u8; 4] = *b"CATA";
+pub const SAVE_VERSION: u32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EnemySave {
+    pub etype: u32,
+    pub hp_frac: f32,   // fraction of the records initial hp so hp rebalances dont brick saves
+    pub pos: Vec2,
+    pub v: Vec2,
+    pub last_attack: f32,
+    pub seed: u32,
+    pub clip: i32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveData {
+    pub game_seed: u32,
+    pub level_seed: u32,
+    pub floor: i32,
+    pub t: f32,
+
+    pub player_hp: f32,
+    pub player_xp: u32,
+    pub player_damage_time: f32,
+    pub player_pos: Vec2,
+    pub player_bible_start: f32,
+    pub player_bible_dir: bool,
+
+    pub enemies: Vec<EnemySave>,
+
+    pub player_fuel: f32,
+    pub fuel_pickups: Vec<Vec2>,
+}
+
+impl SaveData {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = SaveWriter::new();
+        w.put_bytes(&SAVE_MAGIC);
+        w.put_u32(SAVE_VERSION);
+
+        w.put_u32(self.game_seed);
+        w.put_u32(self.level_seed);
+        w.put_i32(self.floor);
+        w.put_f32(self.t);
+
+        w.put_f32(self.player_hp);
+        w.put_u32(self.player_xp);
+        w.put_f32(self.player_damage_time);
+        w.put_vec2(self.player_pos);
+        w.put_f32(self.player_bible_start);
+        w.put_bool(self.player_bible_dir);
+
+        w.put_u32(self.enemies.len() as u32);
+        for e in self.enemies.iter() {
+            w.put_u32(e.etype);
+            w.put_f32(e.hp_frac);
+            w.put_vec2(e.pos);
+            w.put_vec2(e.v);
+            w.put_f32(e.last_attack);
+            w.put_u32(e.seed);
+            w.put_i32(e.clip);
+        }
+
+        w.put_f32(self.player_fuel);
+        w.put_u32(self.fuel_pickups.len() as u32);
+        for p in self.fuel_pickups.iter() {
+            w.put_vec2(*p);
+        }
+        w.buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<SaveData, anyhow::Error> {
+        let mut r = SaveReader::new(bytes);
+        if r.get_bytes(4)? != SAVE_MAGIC {
+            return Err(anyhow::Error::msg("not a cata save file"));
+        }
+        let version = r.get_u32()?;
+        let data = match version {
+            1 => SaveData::read_v1(&mut r)?,
+            2 => SaveData::read_v2(&mut r)?,
+            3 => SaveData::read_v3(&mut r)?,
+            _ => return Err(anyhow::Error::msg(format!("unsupported save version {} (newest is {})", version, SAVE_VERSION))),
+        };
+        Ok(data)
+    }
+
+    // v1 had no xp
+    fn read_v1(r: &mut SaveReader) -> Result<SaveData, anyhow::Error> {
+        SaveData::read_body(r, 1)
+    }
+
+    // v2 had no lamp fuel, old saves get a full lamp and no pickups on the floor
+    fn read_v2(r: &mut SaveReader) -> Result<SaveData, anyhow::Error> {
+        SaveData::read_body(r, 2)
+    }
+
+    fn read_v3(r: &mut SaveReader) -> Result<SaveData, anyhow::Error> {
+        SaveData::read_body(r, 3)
+    }
+
+    fn read_body(r: &mut SaveReader, version: u32) -> Result<SaveData, anyhow::Error> {
+        let game_seed = r.get_u32()?;
+        let level_seed = r.get_u32()?;
+        let floor = r.get_i32()?;
+        let t = r.get_f32()?;
+
+        let player_hp = r.get_f32()?;
+        let player_xp = if version >= 2 { r.get_u32()? } else { 0 };
+        let player_damage_time = r.get_f32()?;
+        let player_pos = r.get_vec2()?;
+        let player_bible_start = r.get_f32()?;
+        let player_bible_dir = r.get_bool()?;
+
+        let n_enemies = r.get_u32()?;
+        let mut enemies = Vec::new();
+        for _ in 0..n_enemies {
+            enemies.push(EnemySave {
+                etype: r.get_u32()?,
+                hp_frac: r.get_f32()?,
+                pos: r.get_vec2()?,
+                v: r.get_vec2()?,
+                last_attack: r.get_f32()?,
+                seed: r.get_u32()?,
+                clip: r.get_i32()?,
+            });
+        }
+
+        let mut player_fuel = 1.0;
+        let mut fuel_pickups = Vec::new();
+        if version >= 3 {
+            player_fuel = r.get_f32()?;
+            let n_pickups = r.get_u32()?;
+            for _ in 0..n_pickups {
+                fuel_pickups.push(r.get_vec2()?);
+            }
+        }
+
+        Ok(SaveData {
+            game_seed,
+            level_seed,
+            floor,
+            t,
+            player_hp,
+            player_xp,
+            player_damage_time,
+            player_pos,
+            player_bible_start,
+            player_bible_dir,
+            enemies,
+            player_fuel,
+            fuel_pickups,
+        })
+    }
+
+    pub fn write_to_file(&self, path: &str) -> Result<(), anyhow::Error> {
+        let mut f = File::create(path)?;
+        f.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn read_from_file(path: &str) -> Result<SaveData, anyhow::Error> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        SaveData::from_bytes(&bytes)
+    }
+}
+
+pub fn save_exists(path: &str) -> bool {
+    std::path::Path::new(path).exists()
+}
+
+pub fn delete_save(path: &str) {
+    if save_exists(path) {
+        if let Err(e) = std::fs::remove_file(path) {
+            println!("couldnt delete save {}: {}", path, e);
+        }
+    }
+}
+
+pub struct SaveWriter {
+    pub buf: Vec<u8>,
+}
+
+impl SaveWriter {
+    pub fn new() -> SaveWriter {
+        SaveWriter { buf: Vec::new() }
+    }
+    pub fn put_bytes(&mut self, b: &[u8]) {
+        self.buf.extend_from_slice(b);
+    }
+    pub fn put_u32(&mut self, x: u32) {
+        self.put_bytes(&x.to_le_bytes());
+    }
+    pub fn put_i32(&mut self, x: i32) {
+        self.put_bytes(&x.to_le_bytes());
+    }
+    pub fn put_f32(&mut self, x: f32) {
+        self.put_bytes(&x.to_le_bytes());
+    }
+    pub fn put_bool(&mut self, x: bool) {
+        self.buf.push(x as u8);
+    }
+    pub fn put_vec2(&mut self, v: Vec2) {
+        self.put_f32(v.x);
+        self.put_f32(v.y);
+    }
+}
+
+pub struct SaveReader<'a> {
+    bytes: &'a [u8],
+    idx: usize,
+}
+
+impl<'a> SaveReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> SaveReader<'a> {
+        SaveReader { bytes, idx: 0 }
+    }
+    pub fn get_bytes(&mut self, n: usize) -> Result<&'a [u8], anyhow::Error> {
+        if self.idx + n > self.bytes.len() {
+            return Err(anyhow::Error::msg(format!("save file truncated at byte {}", self.idx)));
+        }
+        let b = &self.bytes[self.idx..self.idx + n];
+        self.idx += n;
+        Ok(b)
+    }
+    fn get_4(&mut self) -> Result<[u8; 4], anyhow::Error> {
+        let b = self.get_bytes(4)?;
+        Ok([b[0], b[1], b[2], b[3]])
+    }
+    pub fn get_u32(&mut self) -> Result<u32, anyhow::Error> {
+        Ok(u32::from_le_bytes(self.get_4()?))
+    }
+    pub fn get_i32(&mut self) -> Result<i32, anyhow::Error> {
+        Ok(i32::from_le_bytes(self.get_4()?))
+    }
+    pub fn get_f32(&mut self) -> Result<f32, anyhow::Error> {
+        Ok(f32::from_le_bytes(self.get_4()?))
+    }
+    pub fn get_bool(&mut self) -> Result<bool, anyhow::Error> {
+        Ok(self.get_bytes(1)?[0] != 0)
+    }
+    pub fn get_vec2(&mut self) -> Result<Vec2, anyhow::Error> {
+        Ok(Vec2::new(self.get_f32()?, self.get_f32()?))
+    }
+}
+
+#[test]
+fn test_save_roundtrip() {
+    let data = SaveData {
+        game_seed: 1234,
+        level_seed: 5678,
+        floor: 3,
+        t: 12.5,
+        player_hp: 0.4,
+        player_xp: 120,
+        player_damage_time: 11.0,
+        player_pos: Vec2::new(0.3, 0.7),
+        player_bible_start: 10.0,
+        player_bible_dir: true,
+        enemies: vec![EnemySave {
+            etype: 6,
+            hp_frac: 0.5,
+            pos: Vec2::new(0.1, 0.2),
+            v: Vec2::new(0.01, -0.02),
+            last_attack: 12.0,
+            seed: 99,
+            clip: 3,
+        }],
+        player_fuel: 0.25,
+        fuel_pickups: vec![Vec2::new(0.5, 0.5), Vec2::new(0.6, 0.4)],
+    };
+    let bytes = data.to_bytes();
+    assert_eq!(SaveData::from_bytes(&bytes).unwrap(), data);
+
+    // truncated or future saves are errors not panics
+    assert!(SaveData::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    let mut future = bytes.clone();
+    future[4..8].copy_from_slice(&(SAVE_VERSION + 1).to_le_bytes());
+    assert!(SaveData::from_bytes(&future).is_err());
+
+    // a v2 save is the same minus the lamp fuel and pickups on the end
+    let mut v2 = bytes.clone();
+    v2[4..8].copy_from_slice(&2u32.to_le_bytes());
+    v2.truncate(bytes.len() - 4 - 4 - 2 * 8);
+    let migrated = SaveData::from_bytes(&v2).unwrap();
+    assert_eq!(migrated, SaveData { player_fuel: 1.0, fuel_pickups: vec![], ..data.clone() });
+
+    // a v1 save is also missing the xp field
+    let mut v1 = v2.clone();
+    v1[4..8].copy_from_slice(&1u32.to_le_bytes());
+    v1.drain(28..32);
+    let migrated = SaveData::from_bytes(&v1).unwrap();
+    assert_eq!(migrated, SaveData { player_xp: 0, player_fuel: 1.0, fuel_pickups: vec![], ..data });
+}
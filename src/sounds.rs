@@ -0,0 +1,339 @@
+use crate::audio::*;
+use crate::dsp::*;
+use crate::kmath::*;
+use crate::wav::*;
+use std::sync::Arc;
+
+// preset library for game events
+// release: true is a one shot that goes away on its own, release: false holds until the same id is sent again with release: true
+// amp is in db, er is how long the tail is
+
+// voice groups for instance caps
+pub const GROUP_ENEMY_HIT: i32 = 0;
+pub const GROUP_ENEMY_DEATH: i32 = 1;
+pub const GROUP_PROJECTILE_FIRE: i32 = 2;
+pub const GROUP_FOOTSTEP: i32 = 3;
+pub const GROUP_STINGER: i32 = 4;
+
+// ids below ONESHOT_ID_BASE are for sustained sounds, one shots get fresh ids above it
+pub const SOUND_ID_LASER: u32 = 1;
+pub const ONESHOT_ID_BASE: u32 = 1000;
+
+pub const SOUND_BASE: SoundDesc = SoundDesc {
+    f: 220.0,
+    n: 1.0,
+    troll: 1.0,
+    ea: 0.01,
+    ed: 0.1,
+    es: 0.5,
+    er: 0.2,
+    detune: 0.0,
+    voices: 1.0,
+    amp: -20.0,
+    cut: -60.0,
+    cur: 1.0,
+    cdt: 0.0,
+    cdr: 4.0,
+    aout: 1.0,
+    release: true,
+    pan: 0.0,
+    filter: FilterKind::Off,
+    fc: 1000.0,
+    fq: 0.707,
+    fenv: 0.0,
+    sample: -1,
+    pitch: 1.0,
+    pitch_var: 0.0,
+    loop_start: 0.0,
+    loop_end: 0.0,
+    priority: 1,
+    group: -1,
+    max_instances: 0,
+};
+
+pub const LASER_HUM: SoundDesc = SoundDesc {
+    f: 110.0,
+    n: 6.0,
+    troll: 1.5,
+    ea: 0.05,
+    ed: 0.1,
+    es: 0.8,
+    er: 0.15,
+    detune: 12.0,
+    voices: 3.0,
+    amp: -18.0,
+    release: false,
+    filter: FilterKind::Low,
+    fc: 400.0,
+    fq: 2.0,
+    fenv: 2.0,
+    priority: 3,
+    ..SOUND_BASE
+};
+
+pub const BIBLE_WHOOSH: SoundDesc = SoundDesc {
+    f: 180.0,
+    n: 8.0,
+    troll: 1.0,
+    ea: 0.08,
+    ed: 0.15,
+    es: 0.3,
+    er: 0.3,
+    detune: 40.0,
+    voices: 5.0,
+    amp: -22.0,
+    filter: FilterKind::Band,
+    fc: 300.0,
+    fq: 1.5,
+    fenv: 2.5,
+    priority: 2,
+    ..SOUND_BASE
+};
+
+pub const ENEMY_HIT: SoundDesc = SoundDesc {
+    f: 330.0,
+    n: 4.0,
+    troll: 2.0,
+    ea: 0.002,
+    ed: 0.05,
+    es: 0.2,
+    er: 0.06,
+    amp: -20.0,
+    priority: 1,
+    group: GROUP_ENEMY_HIT,
+    max_instances: 3,
+    ..SOUND_BASE
+};
+
+pub const ENEMY_DEATH: SoundDesc = SoundDesc {
+    f: 82.4,
+    n: 10.0,
+    troll: 1.2,
+    ea: 0.005,
+    ed: 0.2,
+    es: 0.3,
+    er: 0.4,
+    detune: 30.0,
+    voices: 4.0,
+    amp: -14.0,
+    filter: FilterKind::Low,
+    fc: 200.0,
+    fq: 1.0,
+    fenv: 3.0,
+    priority: 2,
+    group: GROUP_ENEMY_DEATH,
+    max_instances: 6,
+    ..SOUND_BASE
+};
+
+pub const PLAYER_HURT: SoundDesc = SoundDesc {
+    f: 146.8,
+    n: 8.0,
+    troll: 0.8,
+    ea: 0.002,
+    ed: 0.1,
+    es: 0.5,
+    er: 0.25,
+    detune: 50.0,
+    voices: 2.0,
+    amp: -12.0,
+    priority: 3,
+    ..SOUND_BASE
+};
+
+pub const PROJECTILE_FIRE: SoundDesc = SoundDesc {
+    f: 660.0,
+    n: 3.0,
+    troll: 2.5,
+    ea: 0.002,
+    ed: 0.03,
+    es: 0.1,
+    er: 0.08,
+    detune: 20.0,
+    voices: 2.0,
+    amp: -26.0,
+    filter: FilterKind::High,
+    fc: 400.0,
+    priority: 0,
+    group: GROUP_PROJECTILE_FIRE,
+    max_instances: 4,
+    ..SOUND_BASE
+};
+
+pub const STAIRS_DESCEND: SoundDesc = SoundDesc {
+    f: 55.0,
+    n: 12.0,
+    troll: 1.0,
+    ea: 0.3,
+    ed: 0.6,
+    es: 0.4,
+    er: 1.5,
+    detune: 8.0,
+    voices: 3.0,
+    amp: -12.0,
+    priority: 3,
+    ..SOUND_BASE
+};
+
+pub const FUEL_PICKUP: SoundDesc = SoundDesc {
+    f: 523.3,
+    n: 3.0,
+    troll: 1.5,
+    ea: 0.01,
+    ed: 0.15,
+    es: 0.2,
+    er: 0.3,
+    detune: 5.0,
+    voices: 2.0,
+    amp: -18.0,
+    priority: 2,
+    ..SOUND_BASE
+};
+
+// recorded foley goes in samples/, each with a synth stand in that plays instead when the file is missing or wont load
+// nothing has been recorded yet so every one of these plays its synth for now, dropping a wav at the path picks it up
+pub const SAMPLE_FOOTSTEP: i32 = 0;
+pub const SAMPLE_FILES: [(i32, &str, SoundDesc); 1] = [
+    (SAMPLE_FOOTSTEP, "samples/footstep.wav", FOOTSTEP_SYNTH),
+];
+
+pub const FOOTSTEP: SoundDesc = SoundDesc {
+    sample: SAMPLE_FOOTSTEP,
+    pitch_var: 1.5,
+    ea: 0.0,
+    ed: 0.0,
+    es: 1.0,
+    er: 0.05,
+    amp: -16.0,
+    priority: 0,
+    group: GROUP_FOOTSTEP,
+    max_instances: 2,
+    ..SOUND_BASE
+};
+
+// dull low thump, close enough to a boot on stone
+pub const FOOTSTEP_SYNTH: SoundDesc = SoundDesc {
+    f: 65.0,
+    n: 6.0,
+    troll: 1.5,
+    ea: 0.002,
+    ed: 0.07,
+    es: 0.0,
+    er: 0.03,
+    detune: 35.0,
+    voices: 3.0,
+    amp: -20.0,
+    filter: FilterKind::Low,
+    fc: 300.0,
+    fenv: 1.5,
+    priority: 0,
+    group: GROUP_FOOTSTEP,
+    max_instances: 2,
+    ..SOUND_BASE
+};
+
+// the returned Arcs should be kept alive so the audio thread is never the one that frees them
+pub fn load_samples(audio: &mut Audio) -> Vec<Arc<Sample>> {
+    load_sample_files(audio, &SAMPLE_FILES)
+}
+
+pub fn load_sample_files(audio: &mut Audio, files: &[(i32, &str, SoundDesc)]) -> Vec<Arc<Sample>> {
+    let mut loaded = Vec::new();
+    for (id, path, fallback) in files.iter() {
+        let smp = if std::path::Path::new(path).exists() {
+            Sample::load(path).map_err(|e| println!("couldnt load sample {}: {}", path, e)).ok()
+        } else {
+            None
+        };
+        match smp {
+            Some(smp) => {
+                let smp = Arc::new(smp);
+                audio.load_sample(*id as u32, smp.clone());
+                loaded.push(smp);
+            },
+            None => audio.sample_fallback(*id, *fallback),
+        }
+    }
+    loaded
+}
+
+pub const PRESETS: [(&str, SoundDesc); 9] = [
+    ("laser_hum", LASER_HUM),
+    ("bible_whoosh", BIBLE_WHOOSH),
+    ("enemy_hit", ENEMY_HIT),
+    ("enemy_death", ENEMY_DEATH),
+    ("player_hurt", PLAYER_HURT),
+    ("projectile_fire", PROJECTILE_FIRE),
+    ("stairs_descend", STAIRS_DESCEND),
+    ("fuel_pickup", FUEL_PICKUP),
+    ("footstep_synth", FOOTSTEP_SYNTH),
+];
+
+// cata render-sounds [dir] [--float]
+// writes every preset to dir/name.wav so they can be listened to outside the game
+pub fn render_presets_cli(args: &[String]) -> Result<(), anyhow::Error> {
+    let dir = args.iter().find(|a| !a.starts_with("--")).map(|s| s.as_str()).unwrap_or("sounds");
+    let format = if args.iter().any(|a| a == "--float") { WavFormat::Float32 } else { WavFormat::Pcm16 };
+    std::fs::create_dir_all(dir)?;
+    for (name, sd) in PRESETS.iter() {
+        let path = format!("{}/{}.wav", dir, name);
+        let samples = render_desc(*sd, 1.0, OFFLINE_SAMPLE_RATE);
+        write_wav(&path, &samples, OFFLINE_SAMPLE_RATE, 2, format)?;
+        println!("wrote {} ({:.2}s)", path, samples.len() as f32 / 2.0 / OFFLINE_SAMPLE_RATE as f32);
+    }
+    Ok(())
+}
+
+// one shots need distinct ids or they would retrigger each other in the mixer
+#[derive(Default)]
+pub struct SoundQueue {
+    pub commands: Vec<SoundCommand>,
+    next_id: u32,
+}
+
+impl SoundQueue {
+    pub fn play(&mut self, sd: SoundDesc) {
+        let id = self.oneshot_id();
+        self.commands.push(SoundCommand::new(sd, id));
+    }
+
+    pub fn play_at(&mut self, sd: SoundDesc, pos: Vec2, occluded: bool) {
+        let id = self.oneshot_id();
+        self.commands.push(SoundCommand::new(sd, id).at(pos, occluded));
+    }
+
+    fn oneshot_id(&mut self) -> u32 {
+        let id = ONESHOT_ID_BASE + self.next_id;
+        self.next_id = (self.next_id + 1) % 100000;
+        id
+    }
+
+    pub fn start(&mut self, sd: SoundDesc, id: u32) {
+        self.commands.push(SoundCommand::new(SoundDesc { release: false, ..sd }, id));
+    }
+
+    pub fn stop(&mut self, sd: SoundDesc, id: u32) {
+        self.commands.push(SoundCommand::new(SoundDesc { release: true, ..sd }, id));
+    }
+}
+
+#[test]
+fn test_footstep() {
+    // loaded, the footstep plays the sample
+    let noise: Vec<f32> = (0..4410).map(|i| krand(i) * 2.0 - 1.0).collect();
+    let mut m = Mixer::new(OFFLINE_SAMPLE_RATE as f32);
+    m.load_sample(SAMPLE_FOOTSTEP as u32, Arc::new(Sample { sample_rate: OFFLINE_SAMPLE_RATE as f32, data: noise }));
+    m.handle_command(SoundCommand::new(FOOTSTEP, ONESHOT_ID_BASE));
+    let out: Vec<f32> = (0..4410).map(|_| m.tick().0).collect();
+    let rms = (out.iter().map(|x| x * x).sum::<f32>() / out.len() as f32).sqrt();
+    assert!(rms > 0.01, "loaded footstep is silent: {}", rms);
+
+    // missing, the synth plays instead
+    let mut audio = Audio::recording();
+    assert!(load_sample_files(&mut audio, &[(SAMPLE_FOOTSTEP, "no_such_dir/footstep.wav", FOOTSTEP_SYNTH)]).is_empty());
+    audio.handle_command(SoundCommand::new(FOOTSTEP, ONESHOT_ID_BASE));
+    let sent = audio.recorded_sounds();
+    assert_eq!(sent[0].sd.sample, -1);
+    let out = render_offline(&[(0.0, sent[0])], 0.2, OFFLINE_SAMPLE_RATE);
+    assert!(out.iter().any(|x| x.abs() > 0.01), "synth footstep is silent");
+}
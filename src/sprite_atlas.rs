@@ -0,0 +1,140 @@
+use crate::kimg::*;
+use crate::kmath::*;
+use crate::texture_buffer::*;
+use std::collections::HashMap;
+
+// packs a bunch of images into one texture so a SpriteBatch can draw all of them in one call
+// shelf packing: tallest first, left to right, new shelf when the row is full, height rounded up to a power of two
+// each image gets its edge pixels repeated PADDING deep around it so linear filtering doesnt pull in the neighbours
+// rows are copied top first, the same as the pngs, which is what SpriteBatch expects
+
+const PADDING: usize = 1;
+
+pub struct SpriteAtlas {
+    pub tb: TextureBuffer,
+    regions: HashMap<String, Rect>,
+}
+
+impl SpriteAtlas {
+    pub fn pack(images: &[(String, ImageBufferA)], width: usize) -> SpriteAtlas {
+        let mut order: Vec<usize> = (0..images.len()).collect();
+        order.sort_by_key(|i| std::cmp::Reverse(images[*i].1.h));
+
+        let mut placed = vec![(0, 0); images.len()];
+        let (mut x, mut y, mut shelf_h) = (0, 0, 0);
+        for &i in order.iter() {
+            let im = &images[i].1;
+            let (cw, ch) = (im.w + 2 * PADDING, im.h + 2 * PADDING);
+            assert!(cw <= width, "sprite {} is wider than the atlas", images[i].0);
+            if x + cw > width {
+                y += shelf_h;
+                x = 0;
+                shelf_h = 0;
+            }
+            placed[i] = (x + PADDING, y + PADDING);
+            x += cw;
+            shelf_h = shelf_h.max(ch);
+        }
+        let height = (y + shelf_h).max(1).next_power_of_two();
+
+        let mut tb = TextureBuffer::new(width, height);
+        tb.buf.iter_mut().for_each(|b| *b = 0);
+        let mut regions = HashMap::new();
+        for (i, (name, im)) in images.iter().enumerate() {
+            let (px, py) = placed[i];
+            for j in -(PADDING as i32)..(im.h + PADDING) as i32 {
+                for k in -(PADDING as i32)..(im.w + PADDING) as i32 {
+                    let src = im.get_px(k.clamp(0, im.w as i32 - 1) as usize, j.clamp(0, im.h as i32 - 1) as usize);
+                    let dst = (((py as i32 + j) as usize) * width + (px as i32 + k) as usize) * 4;
+                    tb.buf[dst..dst + 4].copy_from_slice(&[src.0, src.1, src.2, src.3]);
+                }
+            }
+            let (w, h) = (width as f32, height as f32);
+            regions.insert(name.clone(), Rect::new(px as f32 / w, py as f32 / h, im.w as f32 / w, im.h as f32 / h));
+        }
+        SpriteAtlas { tb, regions }
+    }
+
+    // dir/name.png for each name, anything missing comes from placeholder instead
+    pub fn load(dir: &str, names: &[&str], width: usize, placeholder: &impl Fn(&str) -> ImageBufferA) -> SpriteAtlas {
+        let images: Vec<(String, ImageBufferA)> = names.iter()
+            .map(|name| (name.to_string(), ImageBufferA::new_from_file(&format!("{}/{}.png", dir, name)).unwrap_or_else(|| placeholder(name))))
+            .collect();
+        SpriteAtlas::pack(&images, width)
+    }
+
+    pub fn uv(&self, name: &str) -> Rect {
+        match self.regions.get(name) {
+            Some(r) => *r,
+            None => panic!("no sprite called {} in the atlas", name),
+        }
+    }
+}
+
+#[cfg(test)]
+fn solid(w: usize, h: usize, px: (u8, u8, u8, u8)) -> ImageBufferA {
+    let mut im = ImageBufferA::new(w, h);
+    for j in 0..h {
+        for i in 0..w {
+            im.set_px(i, j, px);
+        }
+    }
+    im
+}
+
+#[test]
+fn test_sprite_atlas() {
+    let mut corner = solid(10, 6, (0, 0, 255, 255));
+    corner.set_px(0, 0, (255, 0, 0, 255));
+    let images = vec![
+        ("corner".to_string(), corner),
+        ("tall".to_string(), solid(4, 20, (0, 255, 0, 128))),
+        ("wide".to_string(), solid(28, 3, (255, 255, 255, 255))),
+    ];
+    let atlas = SpriteAtlas::pack(&images, 32);
+    assert_eq!(atlas.tb.w, 32);
+    assert!(atlas.tb.h.is_power_of_two());
+
+    // regions are the right size and dont overlap, padding included
+    let px = |r: Rect| ((r.x * atlas.tb.w as f32).round() as usize, (r.y * atlas.tb.h as f32).round() as usize, (r.w * atlas.tb.w as f32).round() as usize, (r.h * atlas.tb.h as f32).round() as usize);
+    let names = ["corner", "tall", "wide"];
+    for (name, im) in images.iter() {
+        let (_, _, w, h) = px(atlas.uv(name));
+        assert_eq!((w, h), (im.w, im.h));
+    }
+    for a in 0..3 {
+        for b in 0..3 {
+            if a == b { continue; }
+            let (ax, ay, aw, ah) = px(atlas.uv(names[a]));
+            let (bx, by, bw, bh) = px(atlas.uv(names[b]));
+            let apart = ax + aw + PADDING <= bx || bx + bw + PADDING <= ax || ay + ah + PADDING <= by || by + bh + PADDING <= ay;
+            assert!(apart, "{} and {} overlap", names[a], names[b]);
+        }
+    }
+
+    // top left pixel of the image is the top left of its region, and the padding repeats the edge
+    let (x, y, _, _) = px(atlas.uv("corner"));
+    assert_eq!(atlas.tb.get(x as i32, y as i32), Vec4::new(1.0, 0.0, 0.0, 1.0));
+    assert_eq!(atlas.tb.get(x as i32 - 1, y as i32 - 1), Vec4::new(1.0, 0.0, 0.0, 1.0));
+    assert_eq!(atlas.tb.get(x as i32 + 1, y as i32), Vec4::new(0.0, 0.0, 1.0, 1.0));
+    let (x, y, _, _) = px(atlas.uv("tall"));
+    assert_eq!(atlas.tb.buf[(y * atlas.tb.w + x) * 4 + 3], 128);
+
+    // pngs off disk go in as they are, font.png standing in for a sprite
+    let font = ImageBufferA::new_from_file("font.png").unwrap();
+    let loaded = SpriteAtlas::load(".", &["font"], 256, &|_| solid(1, 1, (0, 0, 0, 0)));
+    let r = loaded.uv("font");
+    let (x, y) = ((r.x * loaded.tb.w as f32).round() as usize, (r.y * loaded.tb.h as f32).round() as usize);
+    assert_eq!(((r.w * loaded.tb.w as f32).round() as usize, (r.h * loaded.tb.h as f32).round() as usize), (font.w, font.h));
+    for j in 0..font.h {
+        for i in 0..font.w {
+            let (r, g, b, a) = font.get_px(i, j);
+            let idx = ((y + j) * loaded.tb.w + x + i) * 4;
+            assert_eq!(&loaded.tb.buf[idx..idx + 4], &[r, g, b, a]);
+        }
+    }
+
+    // missing files fall back to the placeholder
+    let atlas = SpriteAtlas::load("no_such_dir", &["a", "b"], 64, &|name| solid(if name == "a" { 8 } else { 16 }, 8, (1, 2, 3, 255)));
+    assert_eq!(atlas.uv("b").w * atlas.tb.w as f32, 16.0);
+}
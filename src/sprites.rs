@@ -0,0 +1,112 @@
+use crate::kimg::*;
+use crate::kmath::*;
+use crate::sprite_atlas::*;
+
+// the games sprites, loaded from sprites/<name>.png when there is one
+// anything missing gets a placeholder painted here out of a few sdf shapes, so the game runs without any art
+
+pub const SPRITE_DIR: &str = "sprites";
+pub const ATLAS_W: usize = 128;
+
+pub const ALTAR: &str = "altar";
+pub const FUEL: &str = "fuel";
+pub const TORCH: &str = "torch";
+pub const BIBLE: &str = "bible";
+pub const SPRITES: [&str; 4] = [ALTAR, FUEL, TORCH, BIBLE];
+
+const PLACEHOLDER_SIZE: usize = 32;
+const OUTLINE: Vec4 = Vec4::new(0.05, 0.04, 0.03, 1.0);
+const GOLD: Vec4 = Vec4::new(1.0, 0.85, 0.4, 1.0);
+
+pub fn load_sprites() -> SpriteAtlas {
+    SpriteAtlas::load(SPRITE_DIR, &SPRITES, ATLAS_W, &placeholder)
+}
+
+fn sd_box(p: Vec2, c: Vec2, half: Vec2) -> f32 {
+    let d = Vec2::new((p.x - c.x).abs() - half.x, (p.y - c.y).abs() - half.y);
+    Vec2::new(d.x.max(0.0), d.y.max(0.0)).magnitude() + d.x.max(d.y).min(0.0)
+}
+
+fn sd_circle(p: Vec2, c: Vec2, r: f32) -> f32 {
+    p.dist(c) - r
+}
+
+// colour over under where d < 0, antialiased over a pixel
+fn over(under: Vec4, d: f32, colour: Vec4) -> Vec4 {
+    let a = (0.5 - d * PLACEHOLDER_SIZE as f32).clamp(0.0, 1.0) * colour.w;
+    let out_a = a + under.w * (1.0 - a);
+    if out_a == 0.0 {
+        return Vec4::new(0.0, 0.0, 0.0, 0.0);
+    }
+    let c = (a * colour + (under.w * (1.0 - a)) * under) / out_a;
+    Vec4::new(c.x, c.y, c.z, out_a)
+}
+
+// p is 0..1 across the sprite with y down
+fn paint(f: &impl Fn(Vec2) -> Vec4) -> ImageBufferA {
+    let n = PLACEHOLDER_SIZE;
+    let mut im = ImageBufferA::new(n, n);
+    let to_u8 = |x: f32| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+    for j in 0..n {
+        for i in 0..n {
+            let c = f(Vec2::new((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32));
+            im.set_px(i, j, (to_u8(c.x), to_u8(c.y), to_u8(c.z), to_u8(c.w)));
+        }
+    }
+    im
+}
+
+fn cross(p: Vec2, c: Vec2, s: f32) -> f32 {
+    sd_box(p, c + Vec2::new(0.0, 0.05 * s), Vec2::new(0.06, 0.32) * s).min(sd_box(p, c - Vec2::new(0.0, 0.08 * s), Vec2::new(0.22, 0.06) * s))
+}
+
+pub fn placeholder(name: &str) -> ImageBufferA {
+    let clear = Vec4::new(0.0, 0.0, 0.0, 0.0);
+    let mid = Vec2::new(0.5, 0.5);
+    match name {
+        ALTAR => paint(&|p| {
+            let block = sd_box(p, mid, Vec2::new(0.42, 0.42));
+            let c = over(clear, block - 0.04, OUTLINE);
+            let c = over(c, block, Vec4::grey(0.5 + 0.1 * (1.0 - p.y)));
+            let c = over(c, cross(p, mid, 1.0) - 0.03, OUTLINE);
+            over(c, cross(p, mid, 1.0), GOLD)
+        }),
+        FUEL => paint(&|p| {
+            let flask = sd_circle(p, Vec2::new(0.5, 0.62), 0.3).min(sd_box(p, Vec2::new(0.5, 0.22), Vec2::new(0.1, 0.14)));
+            let c = over(clear, flask - 0.05, OUTLINE);
+            let c = over(c, flask, Vec4::new(0.9, 0.6, 0.1, 1.0));
+            let c = over(c, sd_box(p, Vec2::new(0.5, 0.12), Vec2::new(0.12, 0.05)), Vec4::new(0.45, 0.3, 0.15, 1.0));
+            over(c, sd_circle(p, Vec2::new(0.4, 0.54), 0.07), Vec4::new(1.0, 0.95, 0.7, 0.8))
+        }),
+        TORCH => paint(&|p| {
+            // teardrop, round at the bottom and pointed at the top
+            let body = sd_circle(p, Vec2::new(0.5, 0.62), 0.25);
+            let tip = ((p.x - 0.5).abs() - 0.25 * (p.y - 0.1) / 0.52).max(0.1 - p.y);
+            let flame = body.min(tip);
+            let c = over(clear, flame, Vec4::new(1.0, 0.45, 0.1, 0.9));
+            let c = over(c, flame + 0.08, Vec4::new(1.0, 0.8, 0.3, 1.0));
+            over(c, sd_circle(p, Vec2::new(0.5, 0.68), 0.1), Vec4::new(1.0, 1.0, 0.85, 1.0))
+        }),
+        BIBLE => paint(&|p| {
+            let book = sd_box(p, mid, Vec2::new(0.34, 0.44));
+            let c = over(clear, book - 0.04, OUTLINE);
+            let c = over(c, book, Vec4::new(0.1, 0.15, 0.85, 1.0));
+            let c = over(c, sd_box(p, Vec2::new(0.2, 0.5), Vec2::new(0.04, 0.44)), Vec4::new(0.05, 0.08, 0.5, 1.0));
+            over(c, cross(p, Vec2::new(0.55, 0.5), 0.8), GOLD)
+        }),
+        _ => paint(&|p| over(clear, sd_box(p, mid, Vec2::new(0.45, 0.45)), Vec4::new(1.0, 0.0, 1.0, 1.0))),
+    }
+}
+
+#[test]
+fn test_sprites() {
+    let atlas = load_sprites();
+    for name in SPRITES {
+        let uv = atlas.uv(name);
+        assert!(uv.w > 0.0 && uv.h > 0.0 && uv.right() <= 1.0 && uv.bot() <= 1.0);
+        // placeholders are solid in the middle and see through in the corners
+        let im = placeholder(name);
+        assert!(im.get_px(16, 16).3 > 200, "{}", name);
+        assert_eq!(im.get_px(0, 0).3, 0, "{}", name);
+    }
+}
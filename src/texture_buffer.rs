@@ -1,5 +1,7 @@
 use crate::kmath::*;
+use std::sync::atomic::{AtomicU32, Ordering};
 
+#[derive(Clone)]
 pub struct TextureBuffer {
     pub buf: Vec<u8>, // RGBARGBARGBA
     pub w: usize,
@@ -28,4 +30,58 @@ impl TextureBuffer {
         self.buf[(idx * 4 + 2) as usize] = (colour.z * 255.0) as u8;
         self.buf[(idx * 4 + 3) as usize] = (colour.w * 255.0) as u8;
     }
-}
\ No newline at end of file
+
+    pub fn get(&self, x: i32, y: i32) -> Vec4 {
+        let idx = ((y * self.w as i32 + x) * 4) as usize;
+        Vec4::new(self.buf[idx] as f32 / 255.0, self.buf[idx + 1] as f32 / 255.0, self.buf[idx + 2] as f32 / 255.0, self.buf[idx + 3] as f32 / 255.0)
+    }
+
+    // copy of a w x h region starting at x, y, for partial uploads
+    pub fn sub(&self, x: usize, y: usize, w: usize, h: usize) -> TextureBuffer {
+        let mut out = TextureBuffer::new(w, h);
+        for j in 0..h {
+            let src = ((y + j) * self.w + x) * 4;
+            out.buf[j * w * 4..(j + 1) * w * 4].copy_from_slice(&self.buf[src..src + w * 4]);
+        }
+        out
+    }
+}
+
+// textures are referred to by handle, the renderer makes the actual texture the first time it sees one in set_texture
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureHandle(u32);
+
+static NEXT_TEXTURE_HANDLE: AtomicU32 = AtomicU32::new(0);
+
+impl TextureHandle {
+    pub fn alloc() -> TextureHandle {
+        TextureHandle(NEXT_TEXTURE_HANDLE.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureFilter {
+    Nearest,
+    Linear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureWrap {
+    Clamp,
+    Repeat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureOptions {
+    pub filter: TextureFilter,
+    pub wrap: TextureWrap,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        TextureOptions {
+            filter: TextureFilter::Nearest,
+            wrap: TextureWrap::Clamp,
+        }
+    }
+}
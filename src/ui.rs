@@ -0,0 +1,315 @@
+use crate::kapp::*;
+use crate::kmath::*;
+use crate::renderers::font_rendering::*;
+
+// immediate mode ui on top of SimpleCanvas and GlyphBuffer
+// make a Ui each frame, call widgets in draw order, then finish() to draw the tooltip on top
+// the only thing that lives between frames is UiState, which remembers what widget the mouse is dragging / holding
+// and whether the mouse was over any ui, so the game can leave clicks on the ui alone
+
+#[derive(Clone, Copy, Debug)]
+pub struct UiTheme {
+    pub panel: Vec4,
+    pub border: Vec4,
+    pub widget: Vec4,
+    pub hot: Vec4,
+    pub active: Vec4,
+    pub text: Vec4,
+    pub text_dim: Vec4,
+    pub accent: Vec4,
+    pub char_h: f32,
+}
+
+impl Default for UiTheme {
+    fn default() -> Self {
+        UiTheme {
+            panel: Vec4::new(0.05, 0.05, 0.07, 1.0),
+            border: Vec4::grey(0.35),
+            widget: Vec4::grey(0.15),
+            hot: Vec4::grey(0.25),
+            active: Vec4::grey(0.4),
+            text: Vec4::grey(0.9),
+            text_dim: Vec4::grey(0.5),
+            accent: Vec4::new(1.0, 0.8, 0.3, 1.0),
+            char_h: 0.02,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct UiState {
+    active: Option<u32>,
+    hovered: bool,          // mouse was over a panel or widget since the last game_mouse
+    lmb_captured: bool,
+    rmb_captured: bool,
+}
+
+impl UiState {
+    // call once a frame before the game acts on the mouse, the ui is drawn after so this is last frames layout
+    // a press that starts over the ui belongs to it until its released, returns whether the game gets (lmb, rmb)
+    pub fn game_mouse(&mut self, inputs: &FrameInputs) -> (bool, bool) {
+        let over = self.hovered || self.active.is_some();
+        self.hovered = false;
+        let capture = |captured: &mut bool, status: KeyStatus| match status {
+            KeyStatus::JustPressed => *captured = over,
+            KeyStatus::Released => *captured = false,
+            _ => {},
+        };
+        capture(&mut self.lmb_captured, inputs.lmb);
+        capture(&mut self.rmb_captured, inputs.rmb);
+        (!self.lmb_captured, !self.rmb_captured)
+    }
+}
+
+pub struct Ui<'a> {
+    pub inputs: &'a FrameInputs,
+    pub outputs: &'a mut FrameOutputs,
+    pub state: &'a mut UiState,
+    pub theme: UiTheme,
+    pub depth: f32,
+    tooltip: Option<String>,
+}
+
+fn widget_id(r: Rect, label: &str) -> u32 {
+    let mut h = khash((r.x * 10000.0) as u32 ^ khash((r.y * 10000.0) as u32));
+    for b in label.bytes() {
+        h = khash(h ^ b as u32);
+    }
+    h
+}
+
+impl<'a> Ui<'a> {
+    pub fn new(inputs: &'a FrameInputs, outputs: &'a mut FrameOutputs, state: &'a mut UiState, depth: f32) -> Ui<'a> {
+        if inputs.lmb == KeyStatus::Released {
+            state.active = None;
+        }
+        Ui {
+            inputs,
+            outputs,
+            state,
+            theme: UiTheme::default(),
+            depth,
+            tooltip: None,
+        }
+    }
+
+    pub fn hovered(&self, r: Rect) -> bool {
+        r.contains(self.inputs.mouse_pos)
+    }
+
+    // hovered, and remembered as the ui having the mouse
+    fn claim(&mut self, r: Rect) -> bool {
+        let hovered = self.hovered(r);
+        self.state.hovered |= hovered;
+        hovered
+    }
+
+    // press starts interacting, release over the widget is a click
+    fn interact(&mut self, r: Rect, id: u32) -> (bool, bool, bool) {
+        let hovered = self.claim(r);
+        if hovered && self.inputs.lmb == KeyStatus::JustPressed {
+            self.state.active = Some(id);
+        }
+        let active = self.state.active == Some(id);
+        let clicked = active && hovered && self.inputs.lmb == KeyStatus::JustReleased;
+        if self.inputs.lmb == KeyStatus::JustReleased && active {
+            self.state.active = None;
+        }
+        (hovered, active, clicked)
+    }
+
+    pub fn panel(&mut self, r: Rect) {
+        self.claim(r);
+        let b = self.theme.char_h * 0.1;
+        self.outputs.canvas.put_rect(r, self.depth, self.theme.border);
+        self.outputs.canvas.put_rect(r.dilate(-b), self.depth + 0.01, self.theme.panel);
+    }
+
+    pub fn label(&mut self, r: Rect, s: &str, align: TextAlign, colour: Vec4) {
+        let layout = TextLayout::new(r.h.min(self.theme.char_h)).align(align);
+        let y = r.y + (r.h - layout.char_h) / 2.0;
+        self.outputs.glyphs.push_layout(Rect::new(r.x, y, r.w, layout.char_h), s, &layout, self.depth + 0.1, colour);
+    }
+
+    pub fn button(&mut self, r: Rect, label: &str) -> bool {
+        self.selectable(r, label, false)
+    }
+
+    // button that can also be drawn as selected, eg for keyboard navigation or lists
+    pub fn selectable(&mut self, r: Rect, label: &str, selected: bool) -> bool {
+        let (hovered, active, clicked) = self.interact(r, widget_id(r, label));
+        let bg = if active {
+            self.theme.active
+        } else if hovered || selected {
+            self.theme.hot
+        } else {
+            self.theme.widget
+        };
+        self.outputs.canvas.put_rect(r, self.depth + 0.02, bg);
+        let colour = if selected { self.theme.accent } else { self.theme.text };
+        self.label(r, label, TextAlign::Center, colour);
+        clicked
+    }
+
+    // drag anywhere on the track, returns true if the value changed
+    pub fn slider(&mut self, r: Rect, label: &str, value: &mut f32, min: f32, max: f32) -> bool {
+        let (hovered, active, _) = self.interact(r, widget_id(r, label));
+        let old = *value;
+        if active {
+            let t = unlerp(self.inputs.mouse_pos.x, r.left(), r.right()).clamp(0.0, 1.0);
+            *value = lerp(min, max, t);
+        }
+        let t = unlerp(*value, min, max).clamp(0.0, 1.0);
+        self.outputs.canvas.put_rect(r, self.depth + 0.02, if hovered || active { self.theme.hot } else { self.theme.widget });
+        self.outputs.canvas.put_rect(r.child(0.0, 0.0, t, 1.0), self.depth + 0.03, self.theme.active);
+        let handle_w = r.h * 0.3;
+        self.outputs.canvas.put_rect(Rect::new(r.x + t * r.w - handle_w / 2.0, r.y, handle_w, r.h), self.depth + 0.04, self.theme.accent);
+        self.label(r, &format!("{} {:.2}", label, *value), TextAlign::Center, self.theme.text);
+        *value != old
+    }
+
+    // rows of selectables, returns true if the selection changed
+    pub fn list(&mut self, r: Rect, items: &[&str], selected: &mut usize) -> bool {
+        let row_h = self.theme.char_h * 1.5;
+        let old = *selected;
+        for (i, item) in items.iter().enumerate() {
+            let row = Rect::new(r.x, r.y + i as f32 * row_h, r.w, row_h);
+            if row.bot() > r.bot() {
+                break;
+            }
+            if self.selectable(row.dilate(-row_h * 0.05), item, i == *selected) {
+                *selected = i;
+            }
+        }
+        *selected != old
+    }
+
+    pub fn progress_bar(&mut self, r: Rect, frac: f32, colour: Vec4) {
+        let frac = frac.clamp(0.0, 1.0);
+        self.outputs.canvas.put_rect(r, self.depth + 0.02, self.theme.border);
+        let inner = r.dilate(-r.h.min(r.w) * 0.1);
+        self.outputs.canvas.put_rect(inner, self.depth + 0.03, self.theme.widget);
+        self.outputs.canvas.put_rect(inner.child(0.0, 0.0, frac, 1.0), self.depth + 0.04, colour);
+    }
+
+    // square slot with a cooldown shade that drains from the top
+    pub fn cooldown_slot(&mut self, r: Rect, label: &str, cooldown_frac: f32, highlighted: bool) {
+        let bg = if highlighted { self.theme.active } else { self.theme.widget };
+        self.outputs.canvas.put_rect(r, self.depth + 0.02, if highlighted { self.theme.accent } else { self.theme.border });
+        self.outputs.canvas.put_rect(r.dilate_pc(-0.08), self.depth + 0.03, bg);
+        let cd = cooldown_frac.clamp(0.0, 1.0);
+        if cd > 0.0 {
+            self.outputs.canvas.put_rect(r.dilate_pc(-0.08).child(0.0, 1.0 - cd, 1.0, cd), self.depth + 0.04, Vec4::new(0.0, 0.0, 0.0, 0.6));
+        }
+        self.label(r, label, TextAlign::Center, self.theme.text);
+    }
+
+    pub fn tooltip_if_hovered(&mut self, r: Rect, s: &str) {
+        if self.hovered(r) {
+            self.tooltip = Some(s.to_owned());
+        }
+    }
+
+    pub fn finish(self) {
+        if let Some(s) = self.tooltip {
+            let depth = self.depth + 1.0;
+            let layout = TextLayout::new(self.theme.char_h);
+            let max_w = self.theme.char_h * 16.0;
+            let (w, h) = layout.measure(&s, max_w);
+            let pad = self.theme.char_h * 0.5;
+            let mut r = Rect::new(self.inputs.mouse_pos.x + pad, self.inputs.mouse_pos.y - h - pad * 3.0, w + pad * 2.0, h + pad * 2.0);
+            // keep it on screen
+            r.x = r.x.min(self.inputs.screen_rect.right() - r.w).max(0.0);
+            r.y = r.y.max(0.0);
+            self.outputs.canvas.put_rect(r, depth, self.theme.border);
+            self.outputs.canvas.put_rect(r.dilate(-pad * 0.2), depth + 0.01, self.theme.panel);
+            self.outputs.glyphs.push_layout(r.dilate(-pad), &s, &layout, depth + 0.1, self.theme.text);
+        }
+    }
+}
+
+// runs one frame of widgets with the given mouse and returns what they returned
+#[cfg(test)]
+fn test_ui_frame<T>(state: &mut UiState, lmb: KeyStatus, mouse: Vec2, f: impl FnOnce(&mut Ui) -> T) -> T {
+    let mut inputs = FrameInputs::new(1.0);
+    inputs.lmb = lmb;
+    inputs.mouse_pos = mouse;
+    let mut outputs = FrameOutputs::new(1.0);
+    let mut ui = Ui::new(&inputs, &mut outputs, state, 1.0);
+    let ret = f(&mut ui);
+    ui.finish();
+    ret
+}
+
+#[test]
+fn test_ui_button() {
+    use KeyStatus::*;
+    let r = Rect::new(0.1, 0.1, 0.2, 0.05);
+    let inside = r.centroid();
+    let outside = Vec2::new(0.5, 0.5);
+    let mut state = UiState::default();
+
+    // press and release inside is a click, on the release
+    assert!(!test_ui_frame(&mut state, Released, inside, |ui| ui.button(r, "ok")));
+    assert!(!test_ui_frame(&mut state, JustPressed, inside, |ui| ui.button(r, "ok")));
+    assert!(!test_ui_frame(&mut state, Pressed, inside, |ui| ui.button(r, "ok")));
+    assert!(test_ui_frame(&mut state, JustReleased, inside, |ui| ui.button(r, "ok")));
+    assert!(!test_ui_frame(&mut state, Released, inside, |ui| ui.button(r, "ok")));
+
+    // dragging off before letting go cancels it
+    test_ui_frame(&mut state, JustPressed, inside, |ui| ui.button(r, "ok"));
+    assert!(!test_ui_frame(&mut state, JustReleased, outside, |ui| ui.button(r, "ok")));
+
+    // and so does a press that started somewhere else
+    test_ui_frame(&mut state, Released, outside, |ui| ui.button(r, "ok"));
+    test_ui_frame(&mut state, JustPressed, outside, |ui| ui.button(r, "ok"));
+    assert!(!test_ui_frame(&mut state, JustReleased, inside, |ui| ui.button(r, "ok")));
+}
+
+#[test]
+fn test_ui_slider() {
+    use KeyStatus::*;
+    let r = Rect::new(0.2, 0.1, 0.4, 0.05);
+    let at = |x: f32| Vec2::new(x, r.y + r.h / 2.0);
+    let mut state = UiState::default();
+    let mut value = 5.0;
+
+    // the press sets the value where it lands, min at the left and max at the right
+    assert!(test_ui_frame(&mut state, JustPressed, at(0.3), |ui| ui.slider(r, "vol", &mut value, 0.0, 10.0)));
+    assert!((value - 2.5).abs() < 1e-4);
+    test_ui_frame(&mut state, Pressed, at(0.5), |ui| ui.slider(r, "vol", &mut value, 0.0, 10.0));
+    assert!((value - 7.5).abs() < 1e-4);
+
+    // dragging past the ends clamps
+    test_ui_frame(&mut state, Pressed, at(0.9), |ui| ui.slider(r, "vol", &mut value, 0.0, 10.0));
+    assert_eq!(value, 10.0);
+    test_ui_frame(&mut state, Pressed, at(0.0), |ui| ui.slider(r, "vol", &mut value, 0.0, 10.0));
+    assert_eq!(value, 0.0);
+
+    // letting go leaves it alone
+    test_ui_frame(&mut state, JustReleased, at(0.0), |ui| ui.slider(r, "vol", &mut value, 0.0, 10.0));
+    assert!(!test_ui_frame(&mut state, Released, at(0.4), |ui| ui.slider(r, "vol", &mut value, 0.0, 10.0)));
+    assert_eq!(value, 0.0);
+}
+
+#[test]
+fn test_ui_list() {
+    use KeyStatus::*;
+    let items = ["sword", "shield", "lamp"];
+    let r = Rect::new(0.1, 0.1, 0.3, 0.5);
+    let row_h = UiTheme::default().char_h * 1.5;
+    let second = Vec2::new(0.2, r.y + row_h * 1.5);
+    let mut state = UiState::default();
+    let mut selected = 0;
+
+    assert!(!test_ui_frame(&mut state, JustPressed, second, |ui| ui.list(r, &items, &mut selected)));
+    assert!(test_ui_frame(&mut state, JustReleased, second, |ui| ui.list(r, &items, &mut selected)));
+    assert_eq!(selected, 1);
+
+    // rows that dont fit arent there to click
+    let short = Rect::new(r.x, r.y, r.w, row_h * 2.0);
+    let third = Vec2::new(0.2, r.y + row_h * 2.5);
+    test_ui_frame(&mut state, JustPressed, third, |ui| ui.list(short, &items, &mut selected));
+    assert!(!test_ui_frame(&mut state, JustReleased, third, |ui| ui.list(short, &items, &mut selected)));
+    assert_eq!(selected, 1);
+}
@@ -3,6 +3,7 @@ use crate::kapp::*;
 use crate::renderers::ct_renderer::*;
 use crate::renderers::simple_renderer::*;
 use crate::renderers::texture_renderer::*;
+use crate::renderers::sprite_renderer::*;
 use crate::renderers::font_rendering::*;
 
 pub struct Video {
@@ -13,6 +14,7 @@ pub struct Video {
 
     pub simple_renderer: SimpleRenderer,
     pub texture_renderer: TextureRenderer,
+    pub sprite_renderer: SpriteRenderer,
     pub ct_renderer: CTRenderer
 }
 
@@ -48,6 +50,7 @@ impl Video {
 
         let simple_renderer = SimpleRenderer::new(&gl);
         let texture_renderer = TextureRenderer::new(&gl);
+        let sprite_renderer = SpriteRenderer::new(&gl);
         let ct_renderer = CTRenderer::new(&gl, "font.png");
 
         Video {
@@ -57,14 +60,21 @@ impl Video {
             yres,
             simple_renderer,
             texture_renderer,
+            sprite_renderer,
             ct_renderer,
         }
     }
 
     pub fn render(&mut self, outputs: &FrameOutputs, a: f32) {
         unsafe {
-            for (buf, idx) in &outputs.set_texture {
-                self.texture_renderer.update(&self.gl, buf, *idx);
+            for handle in &outputs.free_texture {
+                self.texture_renderer.free(&self.gl, *handle);
+            }
+            for (buf, handle, opts) in &outputs.set_texture {
+                self.texture_renderer.update(&self.gl, buf, *handle, *opts);
+            }
+            for (buf, handle, x, y) in &outputs.update_texture {
+                self.texture_renderer.update_sub(&self.gl, buf, *handle, *x, *y);
             }
 
             self.gl.clear_color(0.0, 0.0, 0.0, 1.0);
@@ -72,10 +82,18 @@ impl Video {
             
             self.simple_renderer.render(&self.gl, &outputs.canvas);
 
-            for (r, idx, depth) in &outputs.draw_texture {
-                self.texture_renderer.render(&self.gl, *r, a, *idx, *depth);
+            for (r, uv, handle, depth) in &outputs.draw_texture {
+                self.texture_renderer.render(&self.gl, *r, *uv, a, *handle, *depth);
             }
 
+            for batch in &outputs.sprites {
+                if let Some(texture) = self.texture_renderer.texture(batch.atlas) {
+                    self.sprite_renderer.render(&self.gl, batch, texture);
+                }
+            }
+
+            self.simple_renderer.render(&self.gl, &outputs.overlay);
+
             // self.gl.clear(glow::DEPTH_BUFFER_BIT); 
             
 
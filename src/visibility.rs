@@ -0,0 +1,118 @@
+use crate::kmath::*;
+use crate::renderers::simple_renderer::*;
+
+// what the player can see: sphere trace a fan of rays against the wall sdf
+// cheap enough to redo every frame, and drawn as triangles instead of a per pixel mask
+
+const TRACE_EPS: f32 = 0.0002;
+const TRACE_MAX_STEPS: usize = 96;
+
+pub struct VisibilityPolygon {
+    pub origin: Vec2,
+    pub dirs: Vec<Vec2>,
+    pub dists: Vec<f32>,    // how far each ray got, max_dist if it didnt hit anything
+}
+
+// sdf is distance to the nearest wall, <= 0 inside one
+pub fn trace_ray(sdf: &impl Fn(Vec2) -> f32, origin: Vec2, dir: Vec2, max_dist: f32) -> f32 {
+    let mut t = 0.0;
+    for _ in 0..TRACE_MAX_STEPS {
+        let d = sdf(origin + t * dir);
+        if d < TRACE_EPS {
+            return t;
+        }
+        t += d;
+        if t >= max_dist {
+            return max_dist;
+        }
+    }
+    // grazing a wall, call it a hit where we got to
+    t.min(max_dist)
+}
+
+impl VisibilityPolygon {
+    pub fn new(sdf: &impl Fn(Vec2) -> f32, origin: Vec2, n_rays: usize, max_dist: f32) -> VisibilityPolygon {
+        let mut dirs = Vec::with_capacity(n_rays);
+        let mut dists = Vec::with_capacity(n_rays);
+        for i in 0..n_rays {
+            let theta = i as f32 * 2.0 * PI / n_rays as f32;
+            let dir = Vec2::new(theta.cos(), theta.sin());
+            dirs.push(dir);
+            dists.push(trace_ray(sdf, origin, dir, max_dist));
+        }
+        VisibilityPolygon { origin, dirs, dists }
+    }
+
+    pub fn point(&self, i: usize) -> Vec2 {
+        self.origin + self.dists[i] * self.dirs[i]
+    }
+
+    // between two rays the edge is taken as straight in polar terms, close enough at a few hundred rays
+    pub fn contains(&self, p: Vec2) -> bool {
+        let u = p - self.origin;
+        let n = self.dirs.len();
+        let theta = u.y.atan2(u.x).rem_euclid(2.0 * PI);
+        let f = theta / (2.0 * PI) * n as f32;
+        let i = (f.floor() as usize) % n;
+        let j = (i + 1) % n;
+        u.magnitude() <= lerp(self.dists[i], self.dists[j], f.fract())
+    }
+
+    // darkness overlay: alpha falls off with distance inside the polygon (alpha_at), fades to black over soft past the edge,
+    // then solid black out to far. everything is in world space and goes through to_screen
+    // each wedge between two rays is its own strip so nothing overlaps and blends twice
+    pub fn draw_darkness(&self, canvas: &mut SimpleCanvas, to_screen: &impl Fn(Vec2) -> Vec2, alpha_at: &impl Fn(f32) -> f32, soft: f32, far: f32, depth: f32) {
+        let n = self.dirs.len();
+        let black = |a: f32| Vec4::new(0.0, 0.0, 0.0, a);
+        let o = to_screen(self.origin);
+        for i in 0..n {
+            let j = (i + 1) % n;
+            let (di, dj) = (self.dists[i], self.dists[j]);
+            let (ai, aj) = (alpha_at(di), alpha_at(dj));
+            let edge_i = to_screen(self.point(i));
+            let edge_j = to_screen(self.point(j));
+            let soft_i = to_screen(self.origin + (di + soft) * self.dirs[i]);
+            let soft_j = to_screen(self.origin + (dj + soft) * self.dirs[j]);
+            let far_i = to_screen(self.origin + far.max(di + soft) * self.dirs[i]);
+            let far_j = to_screen(self.origin + far.max(dj + soft) * self.dirs[j]);
+
+            canvas.put_triangle_gradient(o, edge_i, edge_j, depth, black(alpha_at(0.0)), black(ai), black(aj));
+            canvas.put_quad_gradient(edge_i, edge_j, soft_i, soft_j, depth, black(ai), black(aj), black(1.0), black(1.0));
+            canvas.put_quad(soft_i, soft_j, far_i, far_j, depth, black(1.0));
+        }
+    }
+}
+
+#[test]
+fn test_visibility_polygon() {
+    // round room of radius 0.1 with a pillar of radius 0.01 at (0.05, 0)
+    let room = |p: Vec2| 0.1 - p.magnitude();
+    let pillar = |p: Vec2| (p - Vec2::new(0.05, 0.0)).magnitude() - 0.01;
+    let sdf = |p: Vec2| room(p).min(pillar(p));
+
+    let vp = VisibilityPolygon::new(&sdf, Vec2::zero(), 360, 1.0);
+    assert_eq!(vp.dists.len(), 360);
+    // ray 0 points at the pillar, its front face is at 0.04
+    assert!((vp.dists[0] - 0.04).abs() < 0.001);
+    // straight up and behind the pillar sees all the way to the room wall
+    assert!((vp.dists[90] - 0.1).abs() < 0.001);
+    assert!((vp.dists[180] - 0.1).abs() < 0.001);
+    // pillar subtends about 2 * asin(0.01 / 0.05) = 23 degrees, so 11 each side of ray 0 are blocked
+    let blocked = vp.dists.iter().filter(|d| **d < 0.09).count();
+    assert!((21..=25).contains(&blocked), "{}", blocked);
+
+    // in front of the pillar is visible, behind it isnt
+    assert!(vp.contains(Vec2::new(0.03, 0.0)));
+    assert!(!vp.contains(Vec2::new(0.08, 0.0)));
+    assert!(vp.contains(Vec2::new(-0.08, 0.01)));
+    assert!(!vp.contains(Vec2::new(-0.12, 0.0)));
+
+    // max_dist caps open rays
+    let open = VisibilityPolygon::new(&|_p: Vec2| 1.0, Vec2::zero(), 8, 0.12);
+    assert!(open.dists.iter().all(|d| *d == 0.12));
+
+    // every wedge is 3 triangles + 2 for each quad
+    let mut canvas = SimpleCanvas::new(1.0);
+    vp.draw_darkness(&mut canvas, &|p: Vec2| p, &|d: f32| (5.0 * d).min(1.0), 0.01, 1.0, 2.5);
+    assert_eq!(canvas.buf.len(), 360 * 5 * 3 * 7 * 4);
+}
@@ -0,0 +1,145 @@
+use std::fs::File;
+use std::io::Write;
+
+// minimal riff wave writer, interleaved samples in -1..1
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WavFormat {
+    Pcm16,
+    Float32,
+}
+
+pub fn wav_bytes(samples: &[f32], sample_rate: u32, nchannels: u16, format: WavFormat) -> Vec<u8> {
+    let (format_tag, bits): (u16, u16) = match format {
+        WavFormat::Pcm16 => (1, 16),
+        WavFormat::Float32 => (3, 32),
+    };
+    let block_align = nchannels * bits / 8;
+    let data_len = samples.len() as u32 * (bits / 8) as u32;
+
+    let mut buf = Vec::with_capacity(44 + data_len as usize);
+    buf.extend_from_slice(b"RIFF");
+    buf.extend_from_slice(&(36 + data_len).to_le_bytes());
+    buf.extend_from_slice(b"WAVE");
+
+    buf.extend_from_slice(b"fmt ");
+    buf.extend_from_slice(&16u32.to_le_bytes());
+    buf.extend_from_slice(&format_tag.to_le_bytes());
+    buf.extend_from_slice(&nchannels.to_le_bytes());
+    buf.extend_from_slice(&sample_rate.to_le_bytes());
+    buf.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    buf.extend_from_slice(&block_align.to_le_bytes());
+    buf.extend_from_slice(&bits.to_le_bytes());
+
+    buf.extend_from_slice(b"data");
+    buf.extend_from_slice(&data_len.to_le_bytes());
+    for &x in samples {
+        let x = x.clamp(-1.0, 1.0);
+        match format {
+            WavFormat::Pcm16 => buf.extend_from_slice(&((x * i16::MAX as f32).round() as i16).to_le_bytes()),
+            WavFormat::Float32 => buf.extend_from_slice(&x.to_le_bytes()),
+        }
+    }
+    buf
+}
+
+pub fn write_wav(path: &str, samples: &[f32], sample_rate: u32, nchannels: u16, format: WavFormat) -> Result<(), anyhow::Error> {
+    let mut f = File::create(path)?;
+    f.write_all(&wav_bytes(samples, sample_rate, nchannels, format))?;
+    Ok(())
+}
+
+pub struct WavData {
+    pub sample_rate: u32,
+    pub nchannels: u16,
+    pub samples: Vec<f32>,  // interleaved
+}
+
+fn le_u16(b: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([b[i], b[i + 1]])
+}
+
+fn le_u32(b: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]])
+}
+
+// 8/16/24/32 bit pcm and 32 bit float, skips any chunks it doesnt care about
+pub fn parse_wav(b: &[u8]) -> Result<WavData, anyhow::Error> {
+    if b.len() < 12 || &b[0..4] != b"RIFF" || &b[8..12] != b"WAVE" {
+        return Err(anyhow::Error::msg("not a wav file"));
+    }
+    let mut fmt: Option<(u16, u16, u32, u16)> = None;
+    let mut i = 12;
+    while i + 8 <= b.len() {
+        let id = &b[i..i + 4];
+        let len = le_u32(b, i + 4) as usize;
+        let body = i + 8;
+        if body + len > b.len() {
+            return Err(anyhow::Error::msg("wav chunk runs off the end"));
+        }
+        if id == b"fmt " {
+            if len < 16 {
+                return Err(anyhow::Error::msg("wav fmt chunk too short"));
+            }
+            let mut tag = le_u16(b, body);
+            // WAVE_FORMAT_EXTENSIBLE, the real format is the start of the subformat guid
+            if tag == 0xFFFE && len >= 26 {
+                tag = le_u16(b, body + 24);
+            }
+            fmt = Some((tag, le_u16(b, body + 2), le_u32(b, body + 4), le_u16(b, body + 14)));
+        } else if id == b"data" {
+            let (tag, nchannels, sample_rate, bits) = fmt.ok_or_else(|| anyhow::Error::msg("wav data before fmt"))?;
+            let data = &b[body..body + len];
+            let samples: Vec<f32> = match (tag, bits) {
+                (1, 8) => data.iter().map(|x| (*x as f32 - 128.0) / 128.0).collect(),
+                (1, 16) => data.chunks_exact(2).map(|c| i16::from_le_bytes([c[0], c[1]]) as f32 / 32768.0).collect(),
+                (1, 24) => data.chunks_exact(3).map(|c| (i32::from_le_bytes([0, c[0], c[1], c[2]]) >> 8) as f32 / 8388608.0).collect(),
+                (1, 32) => data.chunks_exact(4).map(|c| i32::from_le_bytes([c[0], c[1], c[2], c[3]]) as f32 / 2147483648.0).collect(),
+                (3, 32) => data.chunks_exact(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect(),
+                _ => return Err(anyhow::Error::msg(format!("unsupported wav format {} with {} bits", tag, bits))),
+            };
+            return Ok(WavData { sample_rate, nchannels: nchannels.max(1), samples });
+        }
+        // chunks are padded to even lengths
+        i = body + len + (len & 1);
+    }
+    Err(anyhow::Error::msg("wav has no data chunk"))
+}
+
+pub fn read_wav(path: &str) -> Result<WavData, anyhow::Error> {
+    parse_wav(&std::fs::read(path)?)
+}
+
+#[test]
+fn test_wav_header() {
+    let b = wav_bytes(&[0.0, 1.0, -1.0, 0.5], 44100, 2, WavFormat::Pcm16);
+    assert_eq!(b.len(), 44 + 8);
+    assert_eq!(&b[0..4], b"RIFF");
+    assert_eq!(u32::from_le_bytes([b[4], b[5], b[6], b[7]]), 36 + 8);
+    assert_eq!(u16::from_le_bytes([b[22], b[23]]), 2);
+    assert_eq!(u32::from_le_bytes([b[24], b[25], b[26], b[27]]), 44100);
+    assert_eq!(u32::from_le_bytes([b[28], b[29], b[30], b[31]]), 44100 * 4);
+    assert_eq!(i16::from_le_bytes([b[46], b[47]]), i16::MAX);
+    assert_eq!(i16::from_le_bytes([b[48], b[49]]), -i16::MAX);
+
+    let b = wav_bytes(&[0.25], 48000, 1, WavFormat::Float32);
+    assert_eq!(u16::from_le_bytes([b[20], b[21]]), 3);
+    assert_eq!(f32::from_le_bytes([b[44], b[45], b[46], b[47]]), 0.25);
+}
+
+#[test]
+fn test_wav_roundtrip() {
+    let samples = [0.0, 0.5, -0.5, 0.25, -1.0, 0.75];
+    let w = parse_wav(&wav_bytes(&samples, 22050, 2, WavFormat::Float32)).unwrap();
+    assert_eq!(w.sample_rate, 22050);
+    assert_eq!(w.nchannels, 2);
+    assert_eq!(w.samples, samples);
+
+    let w = parse_wav(&wav_bytes(&samples, 8000, 1, WavFormat::Pcm16)).unwrap();
+    for (a, b) in w.samples.iter().zip(samples.iter()) {
+        assert!((a - b).abs() < 1e-4);
+    }
+
+    assert!(parse_wav(b"RIFF\0\0\0\0WAVE").is_err());
+    assert!(parse_wav(b"not a wav").is_err());
+}